@@ -6,12 +6,14 @@
 
 use crate::{
 	types::{
-		StepHealthResponse, StepProvisionersAsyncPaginator, StepProvisionersPaginator,
-		StepProvisionersResponseRaw, StepVersionResponse,
+		StepHealthResponse, StepProvisioner, StepProvisionersAsyncPaginator,
+		StepProvisionersPaginator, StepProvisionersResponseRaw, StepRootsResponse,
+		StepSignResponse, StepVersionResponse,
 	},
 	TinystepClient,
 };
-use color_eyre::Result;
+use crate::{Error, Result};
+use std::sync::Arc;
 use tracing::instrument;
 
 pub mod root;
@@ -62,23 +64,65 @@ pub async fn version_async(client: &TinystepClient) -> Result<StepVersionRespons
 	client.get_async::<StepVersionResponse>("/version").await
 }
 
+/// `/roots` endpoint - Get every root certificate the server currently
+/// trusts. If you want the client to start trusting new roots as they're
+/// rotated in look at: `TinystepClient::with_root_rotation`.
+///
+/// If you need an async version of this method call: `roots_async`.
+#[instrument]
+pub fn roots(client: &TinystepClient) -> Result<StepRootsResponse> {
+	client.get::<StepRootsResponse>("/roots")
+}
+
+/// `/roots` endpoint - Get every root certificate the server currently
+/// trusts asynchronously.
+#[instrument]
+pub async fn roots_async(client: &TinystepClient) -> Result<StepRootsResponse> {
+	client.get_async::<StepRootsResponse>("/roots").await
+}
+
+/// `/renew` endpoint - Renew the client certificate the client is
+/// currently authenticating with, this requires the client to have been
+/// constructed with an identity.
+///
+/// The private key is not changed by a renewal, so only the new certificate
+/// is returned. If you just want to keep the client's own identity renewed
+/// look at: `TinystepClient::with_identity_renewal`.
+///
+/// If you need an async version of this method call: `renew_async`.
+#[instrument]
+pub fn renew(client: &TinystepClient) -> Result<StepSignResponse> {
+	client.post::<StepSignResponse>("/renew", ())
+}
+
+/// `/renew` endpoint - Renew the client certificate the client is
+/// currently authenticating with asynchronously, this requires the client to
+/// have been constructed with an identity.
+///
+/// The private key is not changed by a renewal, so only the new certificate
+/// is returned.
+#[instrument]
+pub async fn renew_async(client: &TinystepClient) -> Result<StepSignResponse> {
+	client.post_async::<StepSignResponse>("/renew", ()).await
+}
+
 /// `/provisioners` endpoint - Get the list of provisioners for the server
-/// you're talking too. You can specify a `next_cursor` if you'd like too,
-/// alternatively you can use `provisioners` to get an Iterator.
+/// you're talking too. You can specify a `next_cursor`, and a `limit` on the
+/// page size if you'd like too, alternatively you can use `provisioners` to
+/// get an Iterator. Without a `limit` smallstep picks the page size (20 by
+/// default).
 ///
 /// If you need an async version of this method call: `provisioners_raw_async`.
 #[instrument]
 pub fn provisioners_raw(
 	next_cursor: Option<String>,
+	limit: Option<u32>,
 	client: &TinystepClient,
 ) -> Result<StepProvisionersResponseRaw> {
-	let uri_part = if let Some(cursor) = next_cursor {
-		format!("/provisioners?cursor={}", cursor)
-	} else {
-		"/provisioners".to_owned()
-	};
-
-	client.get::<StepProvisionersResponseRaw>(&uri_part)
+	check_provisioners(
+		client,
+		client.get::<StepProvisionersResponseRaw>(&provisioners_uri(next_cursor, limit))?,
+	)
 }
 
 /// `/provisioners` endpoint - Get the list of provisioners for the server
@@ -87,33 +131,127 @@ pub fn provisioners_raw(
 ///
 /// If you need an async version of this method call: `provisioners_async`.
 #[must_use]
-pub fn provisioners(client: &TinystepClient) -> StepProvisionersPaginator {
+pub fn provisioners(client: &TinystepClient) -> StepProvisionersPaginator<'_> {
 	StepProvisionersPaginator::new(client)
 }
 
 /// `/provisioners` endpoint - Get the list of provisioners for the server
-/// you're talking too. You can specify a `next_cursor` if you'd like too,
-/// alternatively you can use `provisioners_async` to get an Stream.
+/// you're talking too. You can specify a `next_cursor`, and a `limit` on the
+/// page size if you'd like too, alternatively you can use
+/// `provisioners_async` to get an Stream.
 #[instrument]
 pub async fn provisioners_raw_async(
 	next_cursor: Option<String>,
+	limit: Option<u32>,
 	client: &TinystepClient,
 ) -> Result<StepProvisionersResponseRaw> {
-	let uri_part = if let Some(cursor) = next_cursor {
-		format!("/provisioners?cursor={}", cursor)
-	} else {
-		"/provisioners".to_owned()
-	};
-
-	client
-		.get_async::<StepProvisionersResponseRaw>(&uri_part)
-		.await
+	check_provisioners(
+		client,
+		client
+			.get_async::<StepProvisionersResponseRaw>(&provisioners_uri(next_cursor, limit))
+			.await?,
+	)
 }
 
 /// `/provisioners` endpoint - Get the list of provisioners for the server
 /// you're talking too. Here you don't need to specify a `next_cursor` as
 /// `StepProvisionersAsyncPaginator` is a Futures stream.
+///
+/// If you need a stream that doesn't borrow the client, e.g. to move into
+/// `tokio::spawn` call: `provisioners_async_owned`.
 #[must_use]
-pub fn provisioners_async(client: &TinystepClient) -> StepProvisionersAsyncPaginator {
+pub fn provisioners_async(client: &TinystepClient) -> StepProvisionersAsyncPaginator<'_> {
 	StepProvisionersAsyncPaginator::new(client)
 }
+
+/// `/provisioners` endpoint - Get the list of provisioners for the server
+/// you're talking too, as a `'static` Futures stream that holds its own
+/// handle to the client.
+#[must_use]
+pub fn provisioners_async_owned(
+	client: Arc<TinystepClient>,
+) -> StepProvisionersAsyncPaginator<'static> {
+	StepProvisionersAsyncPaginator::new_owned(client)
+}
+
+/// Build the uri for a page of `/provisioners`. The cursor comes from the
+/// server, so it's encoded rather than trusted to be url safe.
+fn provisioners_uri(next_cursor: Option<String>, limit: Option<u32>) -> String {
+	let mut query = form_urlencoded::Serializer::new(String::new());
+	if let Some(cursor) = next_cursor {
+		query.append_pair("cursor", &cursor);
+	}
+	if let Some(limit) = limit {
+		query.append_pair("limit", &limit.to_string());
+	}
+
+	let query = query.finish();
+	if query.is_empty() {
+		"/provisioners".to_owned()
+	} else {
+		format!("/provisioners?{}", query)
+	}
+}
+
+/// Fail on any unknown provisioners, if the client wants strict parsing.
+fn check_provisioners(
+	client: &TinystepClient,
+	page: StepProvisionersResponseRaw,
+) -> Result<StepProvisionersResponseRaw> {
+	if client.strict_provisioners {
+		if let Some(StepProvisioner::Unknown { typ, .. }) =
+			page.provisioners.iter().find(|prov| prov.is_unknown())
+		{
+			return Err(Error::UnknownProvisionerType(typ.clone()));
+		}
+	}
+	Ok(page)
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use crate::test_util::serve;
+	use std::path::PathBuf;
+
+	#[test]
+	pub fn test_provisioners_uri() {
+		assert_eq!(provisioners_uri(None, None), "/provisioners");
+		assert_eq!(provisioners_uri(None, Some(5)), "/provisioners?limit=5");
+		assert_eq!(
+			provisioners_uri(Some("a&limit=1#b c".to_owned()), Some(5)),
+			"/provisioners?cursor=a%26limit%3D1%23b+c&limit=5"
+		);
+	}
+
+	#[test]
+	pub fn test_strict_provisioners() {
+		let (base_url, _) = serve(|_| {
+			let page = serde_json::json!({
+				"provisioners": [
+					{"type": "ACME", "name": "acme"},
+					{"type": "FUTURE", "name": "future"},
+				],
+				"nextCursor": "",
+			});
+			(200, page.to_string())
+		});
+		let client = |strict: bool| {
+			TinystepClient::builder()
+				.base_url(base_url.clone())
+				.ca_file(PathBuf::from("/dev/null"))
+				.strict_provisioners(strict)
+				.skip_version_probe(true)
+				.build()
+				.unwrap()
+		};
+
+		match provisioners_raw(None, None, &client(true)) {
+			Err(Error::UnknownProvisionerType(typ)) => assert_eq!(typ, "FUTURE"),
+			other => panic!("expected an unknown provisioner type, got: {:?}", other),
+		}
+		let page = provisioners_raw(None, None, &client(false)).unwrap();
+		assert_eq!(page.provisioners.len(), 2);
+		assert!(page.provisioners[1].is_unknown());
+	}
+}
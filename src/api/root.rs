@@ -1,7 +1,7 @@
 //! API Calls that begin with: `/root/` in their URL.
 
+use crate::Result;
 use crate::{types::StepRootResponse, TinystepClient};
-use color_eyre::Result;
 use tracing::instrument;
 
 /// `/root/{fingerprint}` - Get the root certificate for a particular
@@ -0,0 +1,772 @@
+//! A builder for `TinystepClient`, for when the `new_from_*` constructors
+//! don't expose the option you need.
+
+use crate::{
+	error::{json_or_error, Error, Result},
+	identity::{ClientIdentity, IdentityRenewal},
+	retry::RetryPolicy,
+	rotation::RootRotation,
+	trust::{CachedRoot, RootCache},
+	types::{HostedAuthorityResponse, StepVersionResponse},
+	version::parse_remote_version,
+	TinystepClient,
+};
+use isahc::{
+	config::{CaCertificate, ClientCertificate, PrivateKey, ResolveMap},
+	http::Uri,
+	prelude::*,
+	HttpClient, HttpClientBuilder,
+};
+use std::{
+	net::IpAddr,
+	path::PathBuf,
+	sync::{Arc, Mutex, RwLock},
+	time::Duration,
+};
+use tracing::{instrument, warn};
+
+/// The default user agent sent with every request.
+const DEFAULT_USER_AGENT: &str = concat!("tinystep/", env!("CARGO_PKG_VERSION"));
+
+/// How a `TinystepClient` should figure out which root certificate authority
+/// to trust.
+#[derive(Clone, Debug)]
+pub enum TrustSource {
+	/// A PEM Encoded root certificate authority file on disk.
+	CaFile(PathBuf),
+	/// The fingerprint of the root certificate authority, the root itself will
+	/// be fetched from the smallstep instance, and verified.
+	Fingerprint(String),
+	/// A smallstep hosted authority, the url, and fingerprint of the authority
+	/// will be looked up from the team name.
+	Hosted {
+		/// The team name, the same one you go to when you login at:
+		/// `https://smallstep.com/app/${ this is your team name }`
+		team_name: String,
+		/// The particular authority to use, defaults to `ssh`.
+		authority: Option<String>,
+	},
+}
+
+/// What to do when a smallstep instance requires client authentication, but
+/// no identity was configured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClientAuthPolicy {
+	/// Fail to build the client.
+	Error,
+	/// Log a warning, and build the client anyway. This is the default, since
+	/// the endpoints that don't need authentication still work.
+	#[default]
+	Warn,
+	/// Build the client without saying anything, e.g. when you're building a
+	/// client to obtain an identity in the first place.
+	Ignore,
+}
+
+/// How requests should get to the smallstep instance, used for every request
+/// we make, including fetching roots, and looking up hosted authorities.
+#[derive(Clone)]
+pub(crate) struct NetworkSettings {
+	/// The maximum time a single request may take.
+	pub timeout: Option<Duration>,
+	/// The maximum time establishing a connection may take.
+	pub connect_timeout: Option<Duration>,
+	/// The proxy to send requests through.
+	pub proxy: Option<Uri>,
+	/// Hosts that should never be sent through the proxy.
+	pub no_proxy: Vec<String>,
+	/// Static host, and port to address overrides, like curl's `--resolve`.
+	pub resolve: Vec<(String, u16, IpAddr)>,
+	/// The user agent to send with every request.
+	pub user_agent: String,
+	/// Any extra headers to send with every request.
+	pub default_headers: Vec<(String, String)>,
+}
+
+impl Default for NetworkSettings {
+	fn default() -> Self {
+		Self {
+			timeout: None,
+			connect_timeout: None,
+			proxy: None,
+			no_proxy: Vec::new(),
+			resolve: Vec::new(),
+			user_agent: DEFAULT_USER_AGENT.to_owned(),
+			default_headers: Vec::new(),
+		}
+	}
+}
+
+// The values of default headers are often credentials, and these settings end
+// up in the debug output of every client.
+impl std::fmt::Debug for NetworkSettings {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("NetworkSettings")
+			.field("timeout", &self.timeout)
+			.field("connect_timeout", &self.connect_timeout)
+			.field("proxy", &self.proxy)
+			.field("no_proxy", &self.no_proxy)
+			.field("resolve", &self.resolve)
+			.field("user_agent", &self.user_agent)
+			.field(
+				"default_headers",
+				&self
+					.default_headers
+					.iter()
+					.map(|(name, _)| (name.as_str(), "..."))
+					.collect::<Vec<_>>(),
+			)
+			.finish()
+	}
+}
+
+impl NetworkSettings {
+	/// Start building a http client with these settings, without any TLS
+	/// configuration.
+	pub fn client_builder(&self) -> HttpClientBuilder {
+		let mut bldr = self.unauthenticated_client_builder();
+		for (name, value) in &self.default_headers {
+			bldr = bldr.default_header(name.as_str(), value.as_str());
+		}
+		bldr
+	}
+
+	/// Start building a http client with these settings, but without any of
+	/// the extra default headers. Used for requests to servers we haven't
+	/// verified yet, or that aren't the smallstep instance at all, which
+	/// shouldn't see any credentials in those headers.
+	pub fn unauthenticated_client_builder(&self) -> HttpClientBuilder {
+		let mut bldr = HttpClient::builder().default_header("user-agent", self.user_agent.as_str());
+		if let Some(timeout) = self.timeout {
+			bldr = bldr.timeout(timeout);
+		}
+		if let Some(connect_timeout) = self.connect_timeout {
+			bldr = bldr.connect_timeout(connect_timeout);
+		}
+		if let Some(proxy) = &self.proxy {
+			bldr = bldr.proxy(Some(proxy.clone()));
+		}
+		if !self.no_proxy.is_empty() {
+			bldr = bldr.proxy_blacklist(self.no_proxy.clone());
+		}
+		if !self.resolve.is_empty() {
+			let map = self
+				.resolve
+				.iter()
+				.fold(ResolveMap::new(), |map, (host, port, addr)| {
+					map.add(host, *port, *addr)
+				});
+			bldr = bldr.dns_resolve(map);
+		}
+		bldr
+	}
+}
+
+/// Everything needed to build the underlying http client, kept around so the
+/// http client can be rebuilt when something changes (like the identity).
+#[derive(Clone, Debug)]
+pub(crate) struct HttpSettings {
+	/// The path to the root certificate authority we trust.
+	pub ca_path: PathBuf,
+	/// The client identity presented to the smallstep instance, if any.
+	pub identity: Option<ClientIdentity>,
+	/// How requests get to the smallstep instance.
+	pub network: NetworkSettings,
+}
+
+impl HttpSettings {
+	/// Construct the http client for these settings.
+	pub fn build(&self) -> Result<HttpClient> {
+		let mut bldr = self
+			.network
+			.client_builder()
+			.ssl_ca_certificate(CaCertificate::file(self.ca_path.clone()));
+		if let Some(identity) = &self.identity {
+			bldr = bldr.ssl_client_certificate(ClientCertificate::pem_file(
+				identity.cert_path.clone(),
+				Some(PrivateKey::pem_file(
+					identity.key_path.clone(),
+					identity.key_password.clone(),
+				)),
+			));
+		}
+
+		Ok(bldr.build()?)
+	}
+}
+
+/// A builder for a `TinystepClient`.
+///
+/// At a minimum you need to tell the builder what to trust, and unless you're
+/// using a hosted authority where to find the smallstep instance.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::{path::PathBuf, time::Duration};
+/// # use tinystep::TinystepClientBuilder;
+/// let my_client = TinystepClientBuilder::new()
+///   .base_url("https://ca.internal:9000")
+///   .ca_file(PathBuf::from("/etc/step/certs/root_ca.crt"))
+///   .identity(
+///     PathBuf::from("/etc/step/client.crt"),
+///     PathBuf::from("/etc/step/client.key"),
+///     None,
+///   )
+///   .timeout(Duration::from_secs(30))
+///   .build()
+///   .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct TinystepClientBuilder {
+	/// The Base URL for the smallstep instance.
+	base_url: Option<String>,
+	/// What we should trust.
+	trust: Option<TrustSource>,
+	/// Where to keep fetched roots.
+	root_cache: RootCache,
+	/// The client identity to present.
+	identity: Option<ClientIdentity>,
+	/// How to keep the client identity renewed.
+	identity_renewal: Option<IdentityRenewal>,
+	/// How to follow the authority rotating its root.
+	root_rotation: Option<RootRotation>,
+	/// How requests get to the smallstep instance.
+	network: NetworkSettings,
+	/// If we shouldn't call `/version` while building the client.
+	skip_version_probe: bool,
+	/// What to do when the server requires client authentication, but we
+	/// have no identity.
+	client_auth_policy: ClientAuthPolicy,
+	/// If listing provisioners should fail on a type we don't know about.
+	strict_provisioners: bool,
+	/// When, and how to retry requests.
+	retry_policy: RetryPolicy,
+}
+
+impl Default for TinystepClientBuilder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl TinystepClientBuilder {
+	/// Create a new empty builder.
+	#[must_use]
+	pub fn new() -> Self {
+		Self {
+			base_url: None,
+			trust: None,
+			root_cache: RootCache::default(),
+			identity: None,
+			identity_renewal: None,
+			root_rotation: None,
+			network: NetworkSettings::default(),
+			skip_version_probe: false,
+			client_auth_policy: ClientAuthPolicy::default(),
+			strict_provisioners: false,
+			retry_policy: RetryPolicy::none(),
+		}
+	}
+
+	/// The url of the smallstep instance, this is not needed for hosted
+	/// authorities.
+	#[must_use]
+	pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+		self.base_url = Some(base_url.into());
+		self
+	}
+
+	/// Set what root certificate authority the client should trust.
+	#[must_use]
+	pub fn trust(mut self, trust: TrustSource) -> Self {
+		self.trust = Some(trust);
+		self
+	}
+
+	/// Trust a root certificate authority file on disk.
+	#[must_use]
+	pub fn ca_file(self, ca_bundle: PathBuf) -> Self {
+		self.trust(TrustSource::CaFile(ca_bundle))
+	}
+
+	/// Trust the root certificate authority with this fingerprint, the root
+	/// will be fetched from the smallstep instance.
+	#[must_use]
+	pub fn fingerprint(self, fingerprint: impl Into<String>) -> Self {
+		self.trust(TrustSource::Fingerprint(fingerprint.into()))
+	}
+
+	/// Connect to a smallstep hosted authority, if no authority is specified
+	/// the `ssh` authority will be used.
+	#[must_use]
+	pub fn hosted(self, team_name: impl Into<String>, authority: Option<String>) -> Self {
+		self.trust(TrustSource::Hosted {
+			team_name: team_name.into(),
+			authority,
+		})
+	}
+
+	/// Where to keep roots fetched by fingerprint, see: `trust::RootCache`.
+	#[must_use]
+	pub fn root_cache(mut self, root_cache: RootCache) -> Self {
+		self.root_cache = root_cache;
+		self
+	}
+
+	/// The client identity to present to the smallstep instance. This will be
+	/// a PEM encoded cert/key path, along with an optional password for the key.
+	#[must_use]
+	pub fn identity(
+		mut self,
+		cert_path: PathBuf,
+		key_path: PathBuf,
+		key_password: Option<String>,
+	) -> Self {
+		self.identity = Some(ClientIdentity::new(cert_path, key_path, key_password));
+		self
+	}
+
+	/// Keep the client identity renewed, see:
+	/// `TinystepClient::with_identity_renewal`.
+	#[must_use]
+	pub fn identity_renewal(mut self, renewal: IdentityRenewal) -> Self {
+		self.identity_renewal = Some(renewal);
+		self
+	}
+
+	/// Follow the authority rotating its root, see:
+	/// `TinystepClient::with_root_rotation`.
+	#[must_use]
+	pub fn root_rotation(mut self, rotation: RootRotation) -> Self {
+		self.root_rotation = Some(rotation);
+		self
+	}
+
+	/// The maximum time a single request may take, by default there is no
+	/// limit.
+	#[must_use]
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.network.timeout = Some(timeout);
+		self
+	}
+
+	/// The maximum time establishing a connection may take.
+	#[must_use]
+	pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+		self.network.connect_timeout = Some(timeout);
+		self
+	}
+
+	/// Send all requests through a HTTP(S) proxy. Without this the usual proxy
+	/// environment variables (`https_proxy`, `no_proxy`, etc.) are respected.
+	#[must_use]
+	pub fn proxy(mut self, proxy: Uri) -> Self {
+		self.network.proxy = Some(proxy);
+		self
+	}
+
+	/// Hosts that should be connected to directly, rather than through the
+	/// proxy. Subdomains of each host are also matched.
+	#[must_use]
+	pub fn no_proxy<I, T>(mut self, hosts: I) -> Self
+	where
+		I: IntoIterator<Item = T>,
+		T: Into<String>,
+	{
+		self.network
+			.no_proxy
+			.extend(hosts.into_iter().map(Into::into));
+		self
+	}
+
+	/// Connect to `address` whenever a request is made to `host` on `port`,
+	/// like curl's `--resolve`. The certificate is still verified against
+	/// `host`, so this is useful for reaching the smallstep instance by an
+	/// internal IP.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use std::{net::Ipv4Addr, path::PathBuf};
+	/// # use tinystep::TinystepClient;
+	/// let my_client = TinystepClient::builder()
+	///   .base_url("https://ca.internal")
+	///   .ca_file(PathBuf::from("/etc/step/certs/root_ca.crt"))
+	///   .resolve("ca.internal", 443, Ipv4Addr::new(10, 0, 0, 12))
+	///   .build()
+	///   .unwrap();
+	/// ```
+	#[must_use]
+	pub fn resolve(
+		mut self,
+		host: impl Into<String>,
+		port: u16,
+		address: impl Into<IpAddr>,
+	) -> Self {
+		self.network
+			.resolve
+			.push((host.into(), port, address.into()));
+		self
+	}
+
+	/// Override the user agent, defaults to: `tinystep/${version}`.
+	#[must_use]
+	pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+		self.network.user_agent = user_agent.into();
+		self
+	}
+
+	/// Send an extra header with every request.
+	#[must_use]
+	pub fn default_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+		self.network
+			.default_headers
+			.push((name.into(), value.into()));
+		self
+	}
+
+	/// Retry requests that fail for transient reasons, by default requests
+	/// are never retried. See: `retry::RetryPolicy`. The policy is validated
+	/// when the client is built.
+	#[must_use]
+	pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+		self.retry_policy = retry_policy;
+		self
+	}
+
+	/// Don't call `/version` while building the client. This means building
+	/// the client won't check the smallstep instance is reachable, and the
+	/// remote version won't be known.
+	#[must_use]
+	pub fn skip_version_probe(mut self, skip: bool) -> Self {
+		self.skip_version_probe = skip;
+		self
+	}
+
+	/// What to do when the `/version` probe says the smallstep instance
+	/// requires client authentication, but no identity was configured. By
+	/// default a warning is logged. This does nothing when the version probe
+	/// is skipped.
+	#[must_use]
+	pub fn client_auth_policy(mut self, policy: ClientAuthPolicy) -> Self {
+		self.client_auth_policy = policy;
+		self
+	}
+
+	/// Fail listing provisioners with `Error::UnknownProvisionerType` when the
+	/// server reports a type of provisioner tinystep doesn't know about. By
+	/// default these are returned as a `StepProvisioner::Unknown` instead.
+	#[must_use]
+	pub fn strict_provisioners(mut self, strict: bool) -> Self {
+		self.strict_provisioners = strict;
+		self
+	}
+
+	/// Build the client.
+	///
+	/// # Errors
+	///
+	/// - When no trust source has been configured.
+	/// - When no base url has been configured for a non-hosted authority.
+	/// - When the retry policy's multiplier is less than `1.0`.
+	/// - When looking up a hosted authority, or fetching a root fails.
+	/// - When the http client can't be built.
+	/// - When the `/version` probe fails.
+	/// - When the smallstep instance requires client authentication, no
+	///   identity was configured, and the client auth policy is
+	///   `ClientAuthPolicy::Error`.
+	#[instrument(skip(self), fields(base_url = ?self.base_url, trust = ?self.trust))]
+	pub fn build(self) -> Result<TinystepClient> {
+		if self.retry_policy.multiplier.is_nan() || self.retry_policy.multiplier < 1.0 {
+			return Err(Error::Config(format!(
+				"The retry multiplier must be at least 1.0, not: {}",
+				self.retry_policy.multiplier
+			)));
+		}
+		let trust = self.trust.ok_or_else(|| {
+			Error::Config("A trust source must be configured for a TinystepClient".to_owned())
+		})?;
+		let (base_url, root) = match trust {
+			TrustSource::CaFile(ca_bundle) => (
+				Self::require_base_url(self.base_url)?,
+				CachedRoot::from_path(ca_bundle),
+			),
+			TrustSource::Fingerprint(fingerprint) => {
+				let base_url = Self::require_base_url(self.base_url)?;
+				let root = self
+					.root_cache
+					.root_for(&base_url, &fingerprint, &self.network)?;
+				(base_url, root)
+			}
+			TrustSource::Hosted {
+				team_name,
+				authority,
+			} => {
+				// The headers are meant for the authority, not smallstep's api.
+				let resp = json_or_error::<HostedAuthorityResponse>(
+					self.network
+						.unauthenticated_client_builder()
+						.build()?
+						.get(format!(
+							"https://api.smallstep.com/v1/teams/{}/authorities/{}",
+							team_name,
+							authority.unwrap_or_else(|| "ssh".to_owned())
+						))?,
+				)?;
+				let base_url = resp.url.trim_end_matches('/').to_owned();
+				let root = self
+					.root_cache
+					.root_for(&base_url, &resp.fingerprint, &self.network)?;
+				(base_url, root)
+			}
+		};
+
+		let settings = HttpSettings {
+			ca_path: root.path,
+			identity: self.identity,
+			network: self.network,
+		};
+		let http_client = settings.build()?;
+
+		let mut client = TinystepClient {
+			base_url,
+			remote_version: None,
+			require_client_authentication: None,
+			strict_provisioners: self.strict_provisioners,
+			http_settings: Arc::new(Mutex::new(settings)),
+			_root_guard: root.guard.map(Arc::new),
+			identity_renewal: None,
+			root_rotation: None,
+			retry_policy: self.retry_policy,
+			underlying_http_client: Arc::new(RwLock::new(http_client)),
+		};
+		if let Some(renewal) = self.identity_renewal {
+			client = client.with_identity_renewal(renewal)?;
+		}
+		if let Some(rotation) = self.root_rotation {
+			client = client.with_root_rotation(rotation)?;
+		}
+		if !self.skip_version_probe {
+			let version = client.get::<StepVersionResponse>("/version")?;
+			client.remote_version = parse_remote_version(&version.version);
+			client.require_client_authentication = Some(version.require_client_authentication);
+			if version.require_client_authentication && client.http_settings().identity.is_none() {
+				match self.client_auth_policy {
+					ClientAuthPolicy::Error => {
+						return Err(Error::Config(format!(
+							"{} requires client authentication, but no identity was configured",
+							client.base_url
+						)));
+					}
+					ClientAuthPolicy::Warn => warn!(
+						"{} requires client authentication, but no identity was configured, most requests will fail",
+						client.base_url
+					),
+					ClientAuthPolicy::Ignore => {}
+				}
+			}
+		}
+		Ok(client)
+	}
+
+	/// Ensure a base url was provided, trimming any trailing slash.
+	fn require_base_url(base_url: Option<String>) -> Result<String> {
+		let mut base_url = base_url.ok_or_else(|| {
+			Error::Config("A base url must be configured for a TinystepClient".to_owned())
+		})?;
+		if base_url.ends_with('/') {
+			base_url.pop();
+		}
+		Ok(base_url)
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use crate::test_util::{root_pem, serve};
+	use semver::Version;
+
+	/// Serve `/version` (with `require_client_authentication`), and `/root/*`
+	/// (with `root`) until the test process exits, returning the base url.
+	fn serve_ca(root: String, require_client_authentication: bool) -> String {
+		let (base_url, _) = serve(move |request_line| {
+			let body = if request_line.starts_with("GET /root/") {
+				serde_json::json!({ "ca": root }).to_string()
+			} else {
+				serde_json::json!({
+					"version": "0.18.0",
+					"requireClientAuthentication": require_client_authentication,
+				})
+				.to_string()
+			};
+			(200, body)
+		});
+		base_url
+	}
+
+	/// Everything about a client that should be the same no matter how it was
+	/// constructed.
+	fn describe(client: &TinystepClient) -> String {
+		let settings = client.http_settings();
+		format!(
+			"{} {:?} {:?} {:?} {:?}",
+			client.base_url,
+			settings.ca_path,
+			settings.identity,
+			client.remote_version,
+			client.require_client_authentication
+		)
+	}
+
+	#[test]
+	pub fn test_requires_trust_source() {
+		let is_config_err =
+			|result: Result<TinystepClient>| matches!(result, Err(Error::Config(_)));
+
+		assert!(is_config_err(
+			TinystepClientBuilder::new()
+				.base_url("https://ca.internal")
+				.skip_version_probe(true)
+				.build()
+		));
+		assert!(is_config_err(
+			TinystepClientBuilder::new()
+				.ca_file(PathBuf::from("/dev/null"))
+				.skip_version_probe(true)
+				.build()
+		));
+		assert!(is_config_err(
+			TinystepClientBuilder::new()
+				.fingerprint("abcd")
+				.skip_version_probe(true)
+				.build()
+		));
+
+		let client = TinystepClientBuilder::new()
+			.base_url("https://ca.internal/")
+			.ca_file(PathBuf::from("/dev/null"))
+			.skip_version_probe(true)
+			.build()
+			.unwrap();
+		assert_eq!(client.base_url, "https://ca.internal");
+		assert!(client.remote_version().is_none());
+
+		assert!(is_config_err(
+			TinystepClientBuilder::new()
+				.base_url("https://ca.internal")
+				.ca_file(PathBuf::from("/dev/null"))
+				.retry_policy(RetryPolicy {
+					multiplier: 0.5,
+					..RetryPolicy::default()
+				})
+				.skip_version_probe(true)
+				.build()
+		));
+	}
+
+	#[test]
+	pub fn test_debug_hides_header_values() {
+		let client = TinystepClientBuilder::new()
+			.base_url("https://ca.internal")
+			.ca_file(PathBuf::from("/dev/null"))
+			.default_header("Authorization", "Bearer hunter2")
+			.skip_version_probe(true)
+			.build()
+			.unwrap();
+		let debug = format!("{:?}", client);
+		assert!(debug.contains("Authorization"));
+		assert!(!debug.contains("hunter2"));
+	}
+
+	#[test]
+	pub fn test_client_auth_policy() {
+		let base_url = serve_ca(root_pem("root"), true);
+		let build = |policy: ClientAuthPolicy, identity: bool| {
+			let mut builder = TinystepClient::builder()
+				.base_url(base_url.clone())
+				.ca_file(PathBuf::from("/dev/null"))
+				.client_auth_policy(policy);
+			if identity {
+				builder =
+					builder.identity(PathBuf::from("/dev/null"), PathBuf::from("/dev/null"), None);
+			}
+			builder.build()
+		};
+
+		assert!(matches!(
+			build(ClientAuthPolicy::Error, false),
+			Err(Error::Config(_))
+		));
+		assert!(build(ClientAuthPolicy::Error, true).is_ok());
+		for policy in &[ClientAuthPolicy::Warn, ClientAuthPolicy::Ignore] {
+			let client = build(*policy, false).unwrap();
+			assert_eq!(client.require_client_authentication, Some(true));
+		}
+	}
+
+	#[test]
+	pub fn test_constructors_match_builder() {
+		let root = root_pem("root");
+		let fingerprint = crate::trust::fingerprint_pem(root.as_bytes()).unwrap();
+		let base_url = serve_ca(root, false);
+		let (cert, key) = (PathBuf::from("/dev/null"), PathBuf::from("/dev/null"));
+
+		let from_ca_file =
+			TinystepClient::new_from_ca_file(format!("{}/", base_url), cert.clone()).unwrap();
+		assert_eq!(
+			describe(&from_ca_file),
+			describe(
+				&TinystepClient::builder()
+					.base_url(base_url.clone())
+					.ca_file(cert.clone())
+					.build()
+					.unwrap()
+			)
+		);
+		assert_eq!(from_ca_file.remote_version(), Some(&Version::new(0, 18, 0)));
+
+		assert_eq!(
+			describe(
+				&TinystepClient::new_from_ca_file_with_identity(
+					base_url.clone(),
+					cert.clone(),
+					cert.clone(),
+					key.clone(),
+					Some("password".to_owned()),
+				)
+				.unwrap()
+			),
+			describe(
+				&TinystepClient::builder()
+					.base_url(base_url.clone())
+					.ca_file(cert.clone())
+					.identity(cert.clone(), key, Some("password".to_owned()))
+					.build()
+					.unwrap()
+			)
+		);
+
+		let cache = std::env::temp_dir().join(format!("tinystep-builder-{}", std::process::id()));
+		assert_eq!(
+			describe(
+				&TinystepClient::new_from_fingerprint_with_root_cache(
+					base_url.clone(),
+					&fingerprint,
+					&RootCache::Directory(cache.clone()),
+				)
+				.unwrap()
+			),
+			describe(
+				&TinystepClient::builder()
+					.base_url(base_url)
+					.fingerprint(fingerprint)
+					.root_cache(RootCache::Directory(cache.clone()))
+					.build()
+					.unwrap()
+			)
+		);
+		std::fs::remove_dir_all(&cache).unwrap();
+	}
+}
@@ -0,0 +1,259 @@
+//! The errors tinystep can return.
+//!
+//! Every fallible function in tinystep returns `tinystep::Result`, so you can
+//! match on what actually went wrong. `Error` implements `std::error::Error`,
+//! and is `Send + Sync + 'static`, so `?` still converts it into an
+//! `eyre::Report`, `anyhow::Error`, or `Box<dyn Error>` if you'd rather not.
+
+use crate::{types::StepErrorResponse, version::ServerFeature};
+use isahc::{
+	http::{Response, StatusCode},
+	Body, ResponseExt,
+};
+use semver::Version;
+use serde::de::DeserializeOwned;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// A `Result` defaulting to tinystep's `Error`.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while talking to a smallstep instance.
+///
+/// More variants may be added as tinystep grows, so matches on this need a
+/// wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+	/// We couldn't talk to the smallstep instance at all, or the request
+	/// couldn't be built. The network is down, DNS failed, the TLS handshake
+	/// failed, the request timed out, etc.
+	Transport(isahc::Error),
+	/// The smallstep instance responded with an error status.
+	Http {
+		/// The status code of the response.
+		status: StatusCode,
+		/// The message smallstep gave for the error, or the raw body if it
+		/// wasn't smallstep's usual JSON error.
+		message: String,
+		/// The `X-Request-Id` smallstep tagged the request with, useful for
+		/// finding the request in smallstep's logs.
+		request_id: Option<String>,
+	},
+	/// A root certificate did not match the fingerprint it was expected to
+	/// have.
+	FingerprintMismatch {
+		/// The fingerprint we expected.
+		expected: String,
+		/// The fingerprint of the root we actually received.
+		actual: String,
+	},
+	/// Something else went wrong establishing trust, like being given no
+	/// roots, or an unusable certificate chain.
+	Trust(String),
+	/// An OpenSSL operation failed, usually parsing a certificate or key.
+	OpenSsl(openssl::error::ErrorStack),
+	/// A response, or file could not be decoded (or encoded).
+	Deserialize(serde_json::Error),
+	/// A provisioner type that tinystep doesn't know about.
+	UnknownProvisionerType(String),
+	/// A golang duration that couldn't be parsed, with why.
+	InvalidDuration(String),
+	/// Reading, or writing a file failed.
+	Io(std::io::Error),
+	/// A hook run after writing certificates failed.
+	Hook(String),
+	/// Tinystep, or a configuration file it reads was misconfigured.
+	Config(String),
+	/// The smallstep instance is too old to support something we tried to
+	/// call.
+	UnsupportedServerVersion {
+		/// What we tried to use.
+		feature: ServerFeature,
+		/// The version the smallstep instance reported.
+		version: Version,
+		/// The first version to support `feature`.
+		minimum: Version,
+	},
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		match self {
+			Error::Transport(err) => write!(f, "Failed to talk to smallstep: {}", err),
+			Error::Http {
+				status,
+				message,
+				request_id: Some(request_id),
+			} => write!(
+				f,
+				"Smallstep responded with: {}: {} (request id: {})",
+				status, message, request_id
+			),
+			Error::Http {
+				status, message, ..
+			} => write!(f, "Smallstep responded with: {}: {}", status, message),
+			Error::FingerprintMismatch { expected, actual } => write!(
+				f,
+				"Root certificate fingerprint: {} does not match expected: {}",
+				actual, expected
+			),
+			Error::Trust(message) => write!(f, "Failed to establish trust: {}", message),
+			Error::OpenSsl(err) => write!(f, "OpenSSL error: {}", err),
+			Error::Deserialize(err) => write!(f, "Failed to decode: {}", err),
+			Error::UnknownProvisionerType(typ) => {
+				write!(f, "Unknown provisioner type: {:?}", typ)
+			}
+			Error::InvalidDuration(message) => write!(f, "{}", message),
+			Error::Io(err) => write!(f, "IO error: {}", err),
+			Error::Hook(message) => write!(f, "Post write hook failed: {}", message),
+			Error::Config(message) => write!(f, "Invalid configuration: {}", message),
+			Error::UnsupportedServerVersion {
+				feature,
+				version,
+				minimum,
+			} => write!(
+				f,
+				"{} is unsupported by server version: {}, it requires at least: {}",
+				feature, version, minimum
+			),
+		}
+	}
+}
+
+impl Error {
+	/// The HTTP status smallstep responded with, if this is an `Error::Http`.
+	#[must_use]
+	pub fn status(&self) -> Option<StatusCode> {
+		match self {
+			Error::Http { status, .. } => Some(*status),
+			_ => None,
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Transport(err) => Some(err),
+			Error::OpenSsl(err) => Some(err),
+			Error::Deserialize(err) => Some(err),
+			Error::Io(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<isahc::Error> for Error {
+	fn from(err: isahc::Error) -> Self {
+		Error::Transport(err)
+	}
+}
+
+impl From<isahc::http::Error> for Error {
+	fn from(err: isahc::http::Error) -> Self {
+		Error::Transport(err.into())
+	}
+}
+
+impl From<openssl::error::ErrorStack> for Error {
+	fn from(err: openssl::error::ErrorStack) -> Self {
+		Error::OpenSsl(err)
+	}
+}
+
+impl From<serde_json::Error> for Error {
+	fn from(err: serde_json::Error) -> Self {
+		Error::Deserialize(err)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Self {
+		Error::Io(err)
+	}
+}
+
+/// Decode a JSON response from smallstep, turning any non-success status into
+/// an `Error::Http` rather than failing to decode the error as `D`.
+pub(crate) fn json_or_error<D>(mut resp: Response<Body>) -> Result<D>
+where
+	D: DeserializeOwned,
+{
+	let status = resp.status();
+	if status.is_success() {
+		return Ok(resp.json::<D>()?);
+	}
+
+	let request_id = resp
+		.headers()
+		.get("x-request-id")
+		.and_then(|value| value.to_str().ok())
+		.map(ToOwned::to_owned);
+	let body = resp.text().unwrap_or_default();
+	let message = match serde_json::from_str::<StepErrorResponse>(&body) {
+		Ok(step_error) => step_error.message,
+		Err(_) if body.trim().is_empty() => status
+			.canonical_reason()
+			.unwrap_or("Unknown Error")
+			.to_owned(),
+		Err(_) => body.trim().to_owned(),
+	};
+	Err(Error::Http {
+		status,
+		message,
+		request_id,
+	})
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+
+	#[test]
+	pub fn test_converts_into_eyre() {
+		fn mismatch() -> Result<()> {
+			Err(Error::FingerprintMismatch {
+				expected: "abc".to_owned(),
+				actual: "def".to_owned(),
+			})
+		}
+		fn as_eyre() -> color_eyre::Result<()> {
+			mismatch()?;
+			Ok(())
+		}
+
+		let report = as_eyre().unwrap_err();
+		assert!(matches!(
+			report.downcast_ref::<Error>(),
+			Some(Error::FingerprintMismatch { .. })
+		));
+	}
+
+	#[test]
+	pub fn test_decodes_step_errors() {
+		let resp = Response::builder()
+			.status(401)
+			.header("X-Request-Id", "bq1r6s8qlq6c73cg5vm0")
+			.body(Body::from(r#"{"status":401,"message":"Unauthorized"}"#))
+			.unwrap();
+		match json_or_error::<StepErrorResponse>(resp) {
+			Err(Error::Http {
+				status,
+				message,
+				request_id,
+			}) => {
+				assert_eq!(status, StatusCode::UNAUTHORIZED);
+				assert_eq!(message, "Unauthorized");
+				assert_eq!(request_id.as_deref(), Some("bq1r6s8qlq6c73cg5vm0"));
+			}
+			other => panic!("Expected a HTTP error, got: {:?}", other),
+		}
+
+		let resp = Response::builder()
+			.status(502)
+			.body(Body::from("upstream went away"))
+			.unwrap();
+		let err = json_or_error::<StepErrorResponse>(resp).unwrap_err();
+		assert_eq!(err.status(), Some(StatusCode::BAD_GATEWAY));
+	}
+}
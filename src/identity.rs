@@ -0,0 +1,556 @@
+//! Client identities used to authenticate with a smallstep instance over
+//! mutual TLS, and keeping those identities renewed.
+//!
+//! Identities issued by smallstep are generally short lived, so a long running
+//! process that loads its identity once will eventually find every request
+//! failing. `TinystepClient::with_identity_renewal` renews the identity with
+//! `/renew` before it expires, and swaps the underlying http client over to
+//! the new certificate.
+
+use crate::{
+	error::{json_or_error, Error, Result},
+	sink::{CertificateBundle, FileSink, PrivateDirectory},
+	types::StepSignResponse,
+	TinystepClient,
+};
+use openssl::{asn1::Asn1Time, x509::X509};
+use std::{
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex, PoisonError,
+	},
+	time::{Duration, SystemTime},
+};
+use tracing::{debug, instrument, warn};
+
+/// A PEM Encoded certificate/key pair used to identify ourselves to a
+/// smallstep instance.
+#[derive(Clone)]
+pub struct ClientIdentity {
+	/// The path to the PEM Encoded certificate, optionally followed by its
+	/// intermediates.
+	pub cert_path: PathBuf,
+	/// The path to the PEM Encoded private key.
+	pub key_path: PathBuf,
+	/// The password for the private key, if it is encrypted.
+	pub key_password: Option<String>,
+}
+
+impl std::fmt::Debug for ClientIdentity {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ClientIdentity")
+			.field("cert_path", &self.cert_path)
+			.field("key_path", &self.key_path)
+			.field("key_password", &self.key_password.as_ref().map(|_| "..."))
+			.finish()
+	}
+}
+
+impl ClientIdentity {
+	/// Create a new client identity.
+	#[must_use]
+	pub fn new(cert_path: PathBuf, key_path: PathBuf, key_password: Option<String>) -> Self {
+		Self {
+			cert_path,
+			key_path,
+			key_password,
+		}
+	}
+}
+
+/// Configuration for automatically renewing the identity of a
+/// `TinystepClient`.
+#[derive(Clone, Debug)]
+pub struct IdentityRenewal {
+	/// Renew once less than this fraction of the certificate's lifetime
+	/// remains, this must be between zero, and one. Defaults to a third, the
+	/// same as `step ca renew --daemon`.
+	pub renew_remaining_fraction: f64,
+	/// Write the renewed certificate back over the original certificate path.
+	/// If this is false, the renewed certificate is written to a private
+	/// directory in the temporary directory (removed once the client is
+	/// dropped), since the http client needs to read it from disk.
+	pub write_back: bool,
+}
+
+impl Default for IdentityRenewal {
+	fn default() -> Self {
+		Self {
+			renew_remaining_fraction: 1.0 / 3.0,
+			write_back: false,
+		}
+	}
+}
+
+/// How long to wait before retrying after the first failed renewal, this
+/// doubles with every failure after.
+const RENEWAL_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// The longest to wait before retrying a failed renewal.
+const MAX_RENEWAL_RETRY_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// When the current identity should be renewed, and when it expires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RenewalSchedule {
+	/// When we should next try to renew.
+	renew_at: SystemTime,
+	/// When the current certificate expires.
+	expires_at: SystemTime,
+	/// How many renewals in a row have failed.
+	failures: u32,
+}
+
+/// The current state of automatic renewal for a client, shared between all
+/// clones of a client.
+#[derive(Debug)]
+pub(crate) struct IdentityRenewalState {
+	/// How renewal was configured.
+	settings: IdentityRenewal,
+	/// Where renewed certificates are written.
+	renewed_cert_path: PathBuf,
+	/// The directory holding the renewed certificate when not writing back,
+	/// only kept so it's removed once every clone of the client is dropped.
+	_renewed_cert_dir: Option<PrivateDirectory>,
+	/// When we should next renew.
+	schedule: Mutex<RenewalSchedule>,
+	/// If a renewal is currently happening, so we don't renew more than once.
+	in_flight: AtomicBool,
+}
+
+impl IdentityRenewalState {
+	/// Figure out when a certificate should be renewed, and when it expires.
+	fn schedule_for(cert_pem: &[u8], remaining_fraction: f64) -> Result<RenewalSchedule> {
+		let cert = X509::from_pem(cert_pem)?;
+		let now = Asn1Time::days_from_now(0)?;
+		let lifetime = cert.not_before().diff(cert.not_after())?;
+		let remaining = now.diff(cert.not_after())?;
+		let as_secs = |days: i32, secs: i32| i64::from(days) * 86_400 + i64::from(secs);
+		let from_now = |secs: i64| {
+			let offset = Duration::from_secs(secs.unsigned_abs());
+			if secs < 0 {
+				SystemTime::now() - offset
+			} else {
+				SystemTime::now() + offset
+			}
+		};
+
+		let remaining_secs = as_secs(remaining.days, remaining.secs);
+		#[allow(clippy::cast_possible_truncation)]
+		let renew_in = remaining_secs
+			- (as_secs(lifetime.days, lifetime.secs) as f64 * remaining_fraction) as i64;
+		Ok(RenewalSchedule {
+			renew_at: from_now(renew_in.max(0)),
+			expires_at: from_now(remaining_secs),
+			failures: 0,
+		})
+	}
+
+	/// If it's time to renew, and no-one else is renewing, claim the renewal.
+	fn should_renew(&self) -> bool {
+		let due = SystemTime::now()
+			>= self
+				.schedule
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.renew_at;
+		due && !self.in_flight.swap(true, Ordering::AcqRel)
+	}
+
+	/// Record a failed renewal, backing off before the next attempt. Returns
+	/// the error back if the current identity has already expired, since
+	/// there's nothing left worth presenting.
+	fn renewal_failed(&self, err: Error) -> Result<()> {
+		let mut schedule = self.schedule.lock().unwrap_or_else(PoisonError::into_inner);
+		let backoff = RENEWAL_RETRY_BACKOFF
+			.checked_mul(2_u32.saturating_pow(schedule.failures))
+			.map_or(MAX_RENEWAL_RETRY_BACKOFF, |backoff| {
+				backoff.min(MAX_RENEWAL_RETRY_BACKOFF)
+			});
+		schedule.failures = schedule.failures.saturating_add(1);
+		schedule.renew_at = SystemTime::now() + backoff;
+
+		if SystemTime::now() >= schedule.expires_at {
+			return Err(err);
+		}
+		warn!(
+			"Failed to renew the client identity: {}, retrying in: {:?}, the current identity is still valid",
+			err, backoff
+		);
+		Ok(())
+	}
+}
+
+impl TinystepClient {
+	/// Keep the identity this client was constructed with renewed.
+	///
+	/// Before every request the client checks if its certificate is close to
+	/// expiring, and if it is renews it with `/renew`. Once renewed the
+	/// internal http client is atomically replaced so every clone of this
+	/// client will use the new certificate.
+	///
+	/// If renewing fails the current certificate keeps being used until it
+	/// expires, and renewal is retried with an increasing backoff. Only once
+	/// the certificate has expired do requests fail with the renewal error.
+	///
+	/// # Errors
+	///
+	/// - When this client was not constructed with an identity.
+	/// - When `renew_remaining_fraction` isn't between zero, and one.
+	/// - When the identity certificate can't be read.
+	pub fn with_identity_renewal(mut self, renewal: IdentityRenewal) -> Result<Self> {
+		let identity = self.current_identity().ok_or_else(|| {
+			Error::Config("Identity renewal requires a client identity".to_owned())
+		})?;
+		if !(0.0..=1.0).contains(&renewal.renew_remaining_fraction) {
+			return Err(Error::Config(format!(
+				"The renew remaining fraction must be between 0, and 1, not: {}",
+				renewal.renew_remaining_fraction
+			)));
+		}
+		let schedule = IdentityRenewalState::schedule_for(
+			&std::fs::read(&identity.cert_path)?,
+			renewal.renew_remaining_fraction,
+		)?;
+		let (renewed_cert_path, renewed_cert_dir) = if renewal.write_back {
+			(identity.cert_path.clone(), None)
+		} else {
+			let dir = PrivateDirectory::new("tinystep-identity")?;
+			(dir.path().join("client.crt"), Some(dir))
+		};
+
+		self.identity_renewal = Some(Arc::new(IdentityRenewalState {
+			settings: renewal,
+			renewed_cert_path,
+			_renewed_cert_dir: renewed_cert_dir,
+			schedule: Mutex::new(schedule),
+			in_flight: AtomicBool::new(false),
+		}));
+		Ok(self)
+	}
+
+	/// The identity the client is currently presenting, this will point at the
+	/// renewed certificate once a renewal has happened.
+	#[must_use]
+	pub fn current_identity(&self) -> Option<ClientIdentity> {
+		self.http_settings().identity
+	}
+
+	/// Renew the client's identity right now, regardless of when it expires.
+	///
+	/// For an async function equivalent see `renew_identity_async`.
+	///
+	/// # Errors
+	///
+	/// - When identity renewal has not been turned on.
+	/// - When the smallstep instance refuses to renew the certificate.
+	/// - When the renewed certificate can't be written to disk.
+	#[instrument(skip(self))]
+	pub fn renew_identity(&self) -> Result<()> {
+		self.ensure_identity_renewal()?;
+		let resp = json_or_error::<StepSignResponse>(
+			self.http_client().post(self.construct_url("/renew"), ())?,
+		)?;
+		self.install_renewed_identity(resp)
+	}
+
+	/// Renew the client's identity right now asynchronously, regardless of
+	/// when it expires.
+	///
+	/// # Errors
+	///
+	/// - When identity renewal has not been turned on.
+	/// - When the smallstep instance refuses to renew the certificate.
+	/// - When the renewed certificate can't be written to disk.
+	#[instrument(skip(self))]
+	pub async fn renew_identity_async(&self) -> Result<()> {
+		self.ensure_identity_renewal()?;
+		let resp = json_or_error::<StepSignResponse>(
+			self.http_client()
+				.post_async(self.construct_url("/renew"), ())
+				.await?,
+		)?;
+		self.install_renewed_identity(resp)
+	}
+
+	/// Renew the identity if renewal is turned on, and it's close to expiring.
+	///
+	/// A failed renewal only fails the request once the current identity has
+	/// expired, until then it's logged, and retried after a backoff.
+	pub(crate) fn renew_identity_if_needed(&self) -> Result<()> {
+		match &self.identity_renewal {
+			Some(state) if state.should_renew() => {
+				let result = self
+					.renew_identity()
+					.or_else(|err| state.renewal_failed(err));
+				state.in_flight.store(false, Ordering::Release);
+				result
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Renew the identity asynchronously if renewal is turned on, and it's
+	/// close to expiring. Failures are handled the same as:
+	/// `renew_identity_if_needed`.
+	pub(crate) async fn renew_identity_if_needed_async(&self) -> Result<()> {
+		match &self.identity_renewal {
+			Some(state) if state.should_renew() => {
+				let result = self
+					.renew_identity_async()
+					.await
+					.or_else(|err| state.renewal_failed(err));
+				state.in_flight.store(false, Ordering::Release);
+				result
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Get the renewal state, erroring if renewal hasn't been turned on.
+	fn ensure_identity_renewal(&self) -> Result<&IdentityRenewalState> {
+		self.identity_renewal
+			.as_deref()
+			.ok_or_else(|| Error::Config("Identity renewal has not been turned on".to_owned()))
+	}
+
+	/// Write out a renewed certificate, and swap the http client over to it.
+	fn install_renewed_identity(&self, resp: StepSignResponse) -> Result<()> {
+		let state = self.ensure_identity_renewal()?;
+		let bundle = CertificateBundle::from(resp);
+		let schedule = IdentityRenewalState::schedule_for(
+			bundle.certificate.as_bytes(),
+			state.settings.renew_remaining_fraction,
+		)?;
+
+		FileSink::new(state.renewed_cert_path.clone()).write(&bundle)?;
+		self.update_http_settings(|settings| {
+			if let Some(identity) = settings.identity.as_mut() {
+				identity.cert_path = state.renewed_cert_path.clone();
+			}
+		})?;
+		*state
+			.schedule
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = schedule;
+		debug!(
+			"Renewed client identity, next renewal at: {:?}",
+			schedule.renew_at
+		);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use crate::test_util::{self, new_key, CertOptions};
+	use openssl::pkey::{PKey, Private};
+
+	/// A PEM Encoded self-signed certificate valid between two offsets from
+	/// now.
+	fn cert(key: &PKey<Private>, not_before: i64, not_after: i64) -> String {
+		let cert = test_util::cert(
+			"client",
+			key,
+			CertOptions {
+				ca: false,
+				validity: (not_before, not_after),
+				..CertOptions::default()
+			},
+		);
+		String::from_utf8(cert.to_pem().unwrap()).unwrap()
+	}
+
+	/// A client with renewal turned on, using an identity valid between two
+	/// offsets from now, that can't reach any smallstep instance.
+	fn renewing_client(dir: &std::path::Path, not_before: i64, not_after: i64) -> TinystepClient {
+		renewing_client_with(dir, not_before, not_after, IdentityRenewal::default()).unwrap()
+	}
+
+	fn renewing_client_with(
+		dir: &std::path::Path,
+		not_before: i64,
+		not_after: i64,
+		renewal: IdentityRenewal,
+	) -> Result<TinystepClient> {
+		let key = new_key();
+		std::fs::create_dir_all(dir).unwrap();
+		std::fs::write(dir.join("client.crt"), cert(&key, not_before, not_after)).unwrap();
+		std::fs::write(
+			dir.join("client.key"),
+			key.private_key_to_pem_pkcs8().unwrap(),
+		)
+		.unwrap();
+		TinystepClient::builder()
+			.base_url("http://127.0.0.1:1")
+			.ca_file(PathBuf::from("/dev/null"))
+			.identity(dir.join("client.crt"), dir.join("client.key"), None)
+			.identity_renewal(renewal)
+			.skip_version_probe(true)
+			.build()
+	}
+
+	fn schedule(client: &TinystepClient) -> RenewalSchedule {
+		*client
+			.identity_renewal
+			.as_ref()
+			.unwrap()
+			.schedule
+			.lock()
+			.unwrap()
+	}
+
+	fn assert_near(time: SystemTime, expected: SystemTime) {
+		let diff = time
+			.duration_since(expected)
+			.or_else(|_| expected.duration_since(time))
+			.unwrap();
+		assert!(diff < Duration::from_secs(120), "off by: {:?}", diff);
+	}
+
+	#[test]
+	pub fn test_renew_at() {
+		let key = new_key();
+		let schedule =
+			IdentityRenewalState::schedule_for(cert(&key, 0, 3 * 86_400).as_bytes(), 1.0 / 3.0)
+				.unwrap();
+		assert_near(
+			schedule.renew_at,
+			SystemTime::now() + Duration::from_secs(2 * 86_400),
+		);
+		assert_near(
+			schedule.expires_at,
+			SystemTime::now() + Duration::from_secs(3 * 86_400),
+		);
+
+		let expired = IdentityRenewalState::schedule_for(
+			cert(&key, -3 * 86_400, -3600).as_bytes(),
+			1.0 / 3.0,
+		)
+		.unwrap();
+		assert!(expired.renew_at <= SystemTime::now());
+		assert_near(
+			expired.expires_at,
+			SystemTime::now() - Duration::from_secs(3600),
+		);
+	}
+
+	#[test]
+	pub fn test_should_renew() {
+		let now = SystemTime::now();
+		let state = IdentityRenewalState {
+			settings: IdentityRenewal::default(),
+			renewed_cert_path: PathBuf::from("/dev/null"),
+			_renewed_cert_dir: None,
+			schedule: Mutex::new(RenewalSchedule {
+				renew_at: now - Duration::from_secs(1),
+				expires_at: now + Duration::from_secs(3600),
+				failures: 0,
+			}),
+			in_flight: AtomicBool::new(false),
+		};
+		assert!(state.should_renew());
+		// Someone is already renewing.
+		assert!(!state.should_renew());
+
+		state.in_flight.store(false, Ordering::Release);
+		state.schedule.lock().unwrap().renew_at = now + Duration::from_secs(60);
+		assert!(!state.should_renew());
+	}
+
+	#[test]
+	pub fn test_install_renewed_identity() {
+		let dir = std::env::temp_dir().join(format!("tinystep-identity-{}", std::process::id()));
+		let client = renewing_client(&dir, 0, 3 * 86_400);
+		let renewed = cert(&new_key(), 0, 9 * 86_400);
+		client
+			.install_renewed_identity(StepSignResponse {
+				crt: renewed.clone(),
+				ca: cert(&new_key(), 0, 30 * 86_400),
+				cert_chain: Vec::new(),
+			})
+			.unwrap();
+
+		let state = client.identity_renewal.as_ref().unwrap();
+		let identity = client.current_identity().unwrap();
+		assert_eq!(identity.cert_path, state.renewed_cert_path);
+		assert_eq!(identity.key_path, dir.join("client.key"));
+		assert!(std::fs::read_to_string(&identity.cert_path)
+			.unwrap()
+			.starts_with(&renewed));
+		assert_near(
+			schedule(&client).renew_at,
+			SystemTime::now() + Duration::from_secs(6 * 86_400),
+		);
+
+		// Renewed certificates live in a private directory, that's removed
+		// along with the client.
+		let renewed_dir = state.renewed_cert_path.parent().unwrap().to_path_buf();
+		assert!(renewed_dir.starts_with(std::env::temp_dir()));
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(&renewed_dir)
+				.unwrap()
+				.permissions()
+				.mode();
+			assert_eq!(mode & 0o777, 0o700);
+		}
+		drop(client);
+		assert!(!renewed_dir.exists());
+		assert!(dir.join("client.crt").exists());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	pub fn test_renew_remaining_fraction() {
+		let dir = std::env::temp_dir().join(format!("tinystep-fraction-{}", std::process::id()));
+		for fraction in &[f64::NAN, f64::INFINITY, -0.1, 5.0] {
+			let renewal = IdentityRenewal {
+				renew_remaining_fraction: *fraction,
+				..IdentityRenewal::default()
+			};
+			assert!(
+				matches!(
+					renewing_client_with(&dir, 0, 86_400, renewal),
+					Err(Error::Config(_))
+				),
+				"{}",
+				fraction
+			);
+		}
+		for fraction in &[0.0, 0.5, 1.0] {
+			let renewal = IdentityRenewal {
+				renew_remaining_fraction: *fraction,
+				..IdentityRenewal::default()
+			};
+			assert!(renewing_client_with(&dir, 0, 86_400, renewal).is_ok());
+		}
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	pub fn test_failed_renewal_backs_off() {
+		let dir = std::env::temp_dir().join(format!("tinystep-backoff-{}", std::process::id()));
+		// Due for renewal, but still valid for another minute.
+		let client = renewing_client(&dir, -3 * 86_400, 60);
+		client.renew_identity_if_needed().unwrap();
+		let after_failure = schedule(&client);
+		assert_eq!(after_failure.failures, 1);
+		assert_near(
+			after_failure.renew_at,
+			SystemTime::now() + RENEWAL_RETRY_BACKOFF,
+		);
+		// Not retried until the backoff is over.
+		client.renew_identity_if_needed().unwrap();
+		assert_eq!(schedule(&client).failures, 1);
+
+		let expired = renewing_client(&dir, -3 * 86_400, -60);
+		assert!(expired.renew_identity_if_needed().is_err());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
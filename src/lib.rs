@@ -8,6 +8,13 @@
 //! no examples here, this is temporary, and we plan to add some here as
 //! the library fills out.
 //!
+//! # Errors
+//!
+//! Everything fallible returns a `tinystep::Result`, whose `tinystep::Error`
+//! lets you tell a network failure apart from smallstep rejecting a request,
+//! a fingerprint mismatch, or a response that couldn't be decoded. If you're
+//! using `eyre`, or `anyhow` `?` converts it for you.
+//!
 //! # Pagination
 //!
 //! There are certain endpoints provided by the smallstep server that are
@@ -25,7 +32,7 @@
 //! ```rust
 //! # use tinystep::{api, TinystepClient};
 //! # let my_client = TinystepClient::new_from_hosted("bluestone", Some("certs".to_owned())).unwrap();
-//! let my_page = api::provisioners_raw(None, &my_client).expect("Failed to fetch provisioners.");
+//! let my_page = api::provisioners_raw(None, None, &my_client).expect("Failed to fetch provisioners.");
 //! println!("Do we have two pages of provisioners? {}", !my_page.next_cursor.is_empty());
 //! ```
 //!
@@ -84,19 +91,47 @@
 //!
 //! # assert!(block_on(find_provisioner("GSuite".to_owned(), &my_client)).is_some());
 //! ```
+//!
+//! Looking up a provisioner is common enough that both the iterator, and the
+//! stream have helpers for it, which also only fetch pages until the answer is
+//! found: `find_provisioner`, `provisioners_of_type`, and `find_jwk_by_kid`.
+//!
+//! ```rust
+//! # use tinystep::{api, TinystepClient};
+//! # let my_client = TinystepClient::new_from_hosted("bluestone", Some("certs".to_owned())).unwrap();
+//! let gsuite = api::provisioners(&my_client)
+//!   .find_provisioner("GSuite")
+//!   .expect("Failed to fetch a page.");
+//! assert!(gsuite.is_some());
+//! ```
 
-use color_eyre::Result;
 use isahc::{
-	config::{CaCertificate, ClientCertificate, PrivateKey, SslOption},
-	prelude::*,
-	HttpClient, ResponseExt,
+	http::{Method, Request},
+	HttpClient,
+};
+use std::{
+	path::PathBuf,
+	sync::{Arc, Mutex, PoisonError, RwLock},
 };
-use std::path::PathBuf;
-use tracing::{debug, instrument};
+use tracing::{instrument, warn};
 
 pub mod api;
+pub mod builder;
+pub mod error;
+pub use builder::{ClientAuthPolicy, TinystepClientBuilder, TrustSource};
+pub use error::{Error, Result};
 pub use isahc as http_lib;
+pub mod identity;
+pub mod retry;
+pub mod rotation;
+pub mod sink;
+pub mod step_config;
+#[cfg(test)]
+mod test_util;
+pub mod tls;
+pub mod trust;
 pub mod types;
+pub mod version;
 
 /// `TinystepClient` is a small wrapper around an HTTP Client providing a secure
 /// channel to communicate with a Smallstep Instance. This should fundamentally
@@ -107,7 +142,14 @@ pub mod types;
 /// As such it implements very similar methods to `isahc`'s `HTTPClient`, with
 /// things like `get`/`post`/`put`/`delete`/`send`. Without implementing
 /// anything to do with authentication like `jwk`'s which smallstep can use
-/// to authenticate.
+/// to authenticate. If smallstep responds with an error status, these return
+/// an `Error::Http` with smallstep's message, and request id rather than
+/// failing to decode the response.
+///
+/// Requests can be retried when smallstep is briefly unavailable, see:
+/// `TinystepClientBuilder::retry_policy`. `post`, `put`, and `send` are never
+/// retried since their bodies can't be replayed, use `post_json`, and
+/// `put_json` if you need retries.
 ///
 /// You can construct a tinystep client based off of one of three things:
 ///
@@ -122,100 +164,177 @@ pub mod types;
 ///     you can simply give us your team name, and we will construct the
 ///     client for you. `new_from_hosted`.
 ///
+/// Each of these also has a `_with_identity` variant. If the machine has
+/// already been bootstrapped with the step cli, `from_step_config` reuses its
+/// configuration. For anything else, like
+/// timeouts, proxies, or extra headers use `TinystepClient::builder`.
+///
 /// # Notes
 ///
 /// - `TinystepClient` is entirely thread safe, and the connection pool for it
 ///   is not cheap to create. We recommend creating one, and reusing it
 ///   throughout the code.
 ///
-/// - `TinystepClient` assumes the certificate authority does not rotate.
-///   If your remote version has a remote certificate authority rotation,
-///   you will need to create a new tinystep client.
+/// - `TinystepClient` only trusts the roots it was constructed with, see:
+///   `with_root_rotation` to follow the certificate authority rotating its root.
 ///
 /// - If using a hosted version of smallstep, you will need to provide an
 ///   identity in order to access certain endpoints, such as `/roots`.
-///   You can call these with: `new_from_<method>_with_identity`.
+///   You can call these with: `new_from_<method>_with_identity`. Identities
+///   are usually short lived, see: `with_identity_renewal` to keep them
+///   renewed.
 #[derive(Clone, Debug)]
 pub struct TinystepClient {
 	/// The Base URL for the smallstep client.
 	base_url: String,
-	/// The version of the remote smallstep version.
-	remote_version: String,
+	/// The version of the remote smallstep version, unless the version probe
+	/// was skipped, or the version wasn't semver.
+	remote_version: Option<semver::Version>,
+	/// If the smallstep instance requires client authentication, unless the
+	/// version probe was skipped.
+	require_client_authentication: Option<bool>,
+	/// If listing provisioners should fail on a type we don't know about.
+	strict_provisioners: bool,
+	/// Everything used to build the underlying http client, so it can be
+	/// rebuilt. This is shared between clones, and updated whenever the
+	/// identity, or trusted roots change.
+	http_settings: Arc<Mutex<builder::HttpSettings>>,
+	/// Removes the root certificate authority once every clone of this client
+	/// is gone, when it was only fetched for this client.
+	_root_guard: Option<Arc<trust::EphemeralRoot>>,
+	/// The state of automatic identity renewal, if it has been turned on.
+	identity_renewal: Option<Arc<identity::IdentityRenewalState>>,
+	/// The state of following root rotation, if it has been turned on.
+	root_rotation: Option<Arc<rotation::RootRotationState>>,
+	/// When, and how to retry requests.
+	retry_policy: retry::RetryPolicy,
 	/// The underlying http client used to make network requests to the smallstep
-	/// certificate authority.
-	underlying_http_client: HttpClient,
+	/// certificate authority. This is shared between clones, and replaced
+	/// whenever the client identity is renewed.
+	underlying_http_client: Arc<RwLock<HttpClient>>,
 }
 
 impl TinystepClient {
-	/// Get the root certificate for a particular smallstep instance based off
-	/// it's fingerprint. This writes it out to a file since isahc (because of
-	/// curl) requires a filepath.
-	fn get_root_certificate_from_fingerprint(base_url: &str, fingerprint: &str) -> Result<PathBuf> {
-		// This URL is signed by the root certificate we're fetching.
-		let req = Request::get(format!("{}/root/{}", base_url, fingerprint))
-			.ssl_options(SslOption::DANGER_ACCEPT_INVALID_CERTS)
-			.body(())?;
-		let resp = isahc::send(req)?.json::<types::StepRootResponse>()?;
-
-		let digest = {
-			use openssl::{hash::MessageDigest, x509::X509};
-			let raw_digest = X509::from_pem(resp.ca.as_bytes())?.digest(MessageDigest::sha256())?;
-			hex::encode(raw_digest).to_lowercase()
-		};
-
-		debug!(
-			"Received Digest: [{}] from base url: [{}], comparing to argument: [{}]",
-			digest, base_url, fingerprint
-		);
-		if digest != fingerprint {
-			return Err(color_eyre::eyre::eyre!(format!(
-				"Root certificate for: {} does not match fingerprint: {}",
-				base_url, fingerprint
-			)));
-		}
+	/// Start building a client, for when none of the `new_from_*` constructors
+	/// expose the options you need. See: `TinystepClientBuilder`.
+	#[must_use]
+	pub fn builder() -> TinystepClientBuilder {
+		TinystepClientBuilder::new()
+	}
 
-		{
-			use std::{fs::OpenOptions, io::prelude::*};
-			let file_str = format!("smallstep-ca-{}.pem", fingerprint);
-			let mut fd = OpenOptions::new()
-				.create(true)
-				.truncate(true)
-				.write(true)
-				.read(false)
-				.open(&file_str)?;
-			fd.write_all(resp.ca.as_bytes())?;
-			Ok(PathBuf::from(&file_str))
-		}
+	/// A copy of the settings the current http client was built from.
+	pub(crate) fn http_settings(&self) -> builder::HttpSettings {
+		self.http_settings
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+
+	/// Change the http settings, and atomically swap every clone of this client
+	/// over to a http client built from them. If the new http client can't be
+	/// built nothing changes.
+	pub(crate) fn update_http_settings(
+		&self,
+		update: impl FnOnce(&mut builder::HttpSettings),
+	) -> Result<()> {
+		// Settings are only ever replaced whole, so a panic while holding either
+		// lock can't leave them half updated.
+		let mut settings = self
+			.http_settings
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+		let mut updated = settings.clone();
+		update(&mut updated);
+		let http_client = updated.build()?;
+		*self
+			.underlying_http_client
+			.write()
+			.unwrap_or_else(PoisonError::into_inner) = http_client;
+		*settings = updated;
+		Ok(())
+	}
+
+	/// Get a handle to the current underlying http client.
+	fn http_client(&self) -> HttpClient {
+		self.underlying_http_client
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
 	}
 
-	/// Construct a HTTP Client from the base URL, and the path to the
-	/// certificate authority.
-	fn http_client_from_certs(
-		ca_path: PathBuf,
-		client_path: Option<(PathBuf, (PathBuf, Option<String>))>,
-	) -> Result<HttpClient> {
-		let mut bldr = HttpClient::builder()
-			.default_headers(&[(
-				"user-agent",
-				concat!("tinystep/", env!("CARGO_PKG_VERSION")),
-			)])
-			.ssl_ca_certificate(CaCertificate::file(ca_path));
-
-		if let Some(cert_pair) = client_path {
-			bldr = bldr.ssl_client_certificate(ClientCertificate::pem_file(
-				cert_pair.0,
-				Some(PrivateKey::pem_file((cert_pair.1).0, (cert_pair.1).1)),
-			));
+	/// Everything that happens before a request is sent, renewing our
+	/// identity, and checking for new roots when those are turned on.
+	fn before_request(&self) -> Result<()> {
+		self.renew_identity_if_needed()?;
+		self.refresh_roots_if_needed();
+		Ok(())
+	}
+
+	/// Everything that happens before an async request is sent.
+	async fn before_request_async(&self) -> Result<()> {
+		self.renew_identity_if_needed_async().await?;
+		self.refresh_roots_if_needed_async().await;
+		Ok(())
+	}
+
+	/// Send a request built by `build_req`, retrying it according to the
+	/// retry policy. The request is rebuilt for every attempt.
+	fn execute<D>(&self, build_req: impl Fn() -> Result<Request<Vec<u8>>>) -> Result<D>
+	where
+		D: serde::de::DeserializeOwned,
+	{
+		self.before_request()?;
+		let mut attempt = 1;
+		loop {
+			let req = build_req()?;
+			let method = req.method().clone();
+			let result = self.http_client().send(req);
+			match self.retry_policy.retry_delay(&method, attempt, &result) {
+				Some(delay) => {
+					warn!(
+						"Request failed, retrying in: {:?} (attempt {})",
+						delay, attempt
+					);
+					std::thread::sleep(delay);
+					attempt += 1;
+				}
+				None => return error::json_or_error(result?),
+			}
 		}
+	}
 
-		Ok(bldr.build()?)
+	/// Send a request built by `build_req` asynchronously, retrying it
+	/// according to the retry policy. The request is rebuilt for every attempt.
+	async fn execute_async<D>(&self, build_req: impl Fn() -> Result<Request<Vec<u8>>>) -> Result<D>
+	where
+		D: serde::de::DeserializeOwned,
+	{
+		self.before_request_async().await?;
+		let mut attempt = 1;
+		loop {
+			let req = build_req()?;
+			let method = req.method().clone();
+			let result = self.http_client().send_async(req).await;
+			match self.retry_policy.retry_delay(&method, attempt, &result) {
+				Some(delay) => {
+					warn!(
+						"Request failed, retrying in: {:?} (attempt {})",
+						delay, attempt
+					);
+					futures_timer::Delay::new(delay).await;
+					attempt += 1;
+				}
+				None => return error::json_or_error(result?),
+			}
+		}
 	}
 
-	/// Get the version for a smallstep instance.
-	fn get_version(base_url: &str, client: &HttpClient) -> Result<types::StepVersionResponse> {
-		Ok(client
-			.get(format!("{}/version", base_url))?
-			.json::<types::StepVersionResponse>()?)
+	/// Build a request with no body to a particular api route.
+	fn empty_request(&self, method: Method, uri_part: &str) -> Result<Request<Vec<u8>>> {
+		Ok(Request::builder()
+			.method(method)
+			.uri(self.construct_url(uri_part))
+			.body(Vec::new())?)
 	}
 
 	/// Connect to any smallstep instance.
@@ -230,18 +349,11 @@ impl TinystepClient {
 	/// version of smallstep, you can avoid it alltogether with:
 	/// `new_from_hosted`.
 	#[instrument]
-	pub fn new_from_ca_file(mut base_url: String, ca_bundle: PathBuf) -> Result<Self> {
-		if base_url.ends_with('/') {
-			base_url.pop();
-		}
-		let http_client = Self::http_client_from_certs(ca_bundle, None)?;
-		let version = Self::get_version(&base_url, &http_client)?;
-
-		Ok(Self {
-			base_url,
-			remote_version: version.version,
-			underlying_http_client: http_client,
-		})
+	pub fn new_from_ca_file(base_url: String, ca_bundle: PathBuf) -> Result<Self> {
+		Self::builder()
+			.base_url(base_url)
+			.ca_file(ca_bundle)
+			.build()
 	}
 
 	/// Connect to any smallstep instance, with a client identity.
@@ -259,28 +371,19 @@ impl TinystepClient {
 	/// can look at: `new_from_fingerprint`, and if you're running a hosted
 	/// version of smallstep, you can avoid it alltogether with:
 	/// `new_from_hosted`.
-	#[instrument]
+	#[instrument(skip(client_pass))]
 	pub fn new_from_ca_file_with_identity(
-		mut base_url: String,
+		base_url: String,
 		ca_bundle: PathBuf,
 		client_cert_path: PathBuf,
 		client_key_path: PathBuf,
 		client_pass: Option<String>,
 	) -> Result<Self> {
-		if base_url.ends_with('/') {
-			base_url.pop();
-		}
-		let http_client = Self::http_client_from_certs(
-			ca_bundle,
-			Some((client_cert_path, (client_key_path, client_pass))),
-		)?;
-		let version = Self::get_version(&base_url, &http_client)?;
-
-		Ok(Self {
-			base_url,
-			remote_version: version.version,
-			underlying_http_client: http_client,
-		})
+		Self::builder()
+			.base_url(base_url)
+			.ca_file(ca_bundle)
+			.identity(client_cert_path, client_key_path, client_pass)
+			.build()
 	}
 
 	/// Connect to any smallstep instance with only the CA fingerprint.
@@ -304,19 +407,11 @@ impl TinystepClient {
 	///	).unwrap();
 	/// ```
 	#[instrument]
-	pub fn new_from_fingerprint(mut base_url: String, fingerprint: &str) -> Result<Self> {
-		if base_url.ends_with('/') {
-			base_url.pop();
-		}
-		let root_cert_path = Self::get_root_certificate_from_fingerprint(&base_url, fingerprint)?;
-		let http_client = Self::http_client_from_certs(root_cert_path, None)?;
-		let version = Self::get_version(&base_url, &http_client)?;
-
-		Ok(Self {
-			base_url,
-			remote_version: version.version,
-			underlying_http_client: http_client,
-		})
+	pub fn new_from_fingerprint(base_url: String, fingerprint: &str) -> Result<Self> {
+		Self::builder()
+			.base_url(base_url)
+			.fingerprint(fingerprint)
+			.build()
 	}
 
 	/// Connect to any smallstep instance with only the CA fingerprint, and a
@@ -334,29 +429,50 @@ impl TinystepClient {
 	///
 	/// We will fetch the actual certificate authority from smallstep, validating
 	/// it against the fingerprint to ensure we're talking to the correct party.
-	#[instrument]
+	#[instrument(skip(client_pass))]
 	pub fn new_from_fingerprint_with_identity(
-		mut base_url: String,
+		base_url: String,
 		fingerprint: &str,
 		client_cert_path: PathBuf,
 		client_key_path: PathBuf,
 		client_pass: Option<String>,
 	) -> Result<Self> {
-		if base_url.ends_with('/') {
-			base_url.pop();
-		}
-		let root_cert_path = Self::get_root_certificate_from_fingerprint(&base_url, fingerprint)?;
-		let http_client = Self::http_client_from_certs(
-			root_cert_path,
-			Some((client_cert_path, (client_key_path, client_pass))),
-		)?;
-		let version = Self::get_version(&base_url, &http_client)?;
+		Self::builder()
+			.base_url(base_url)
+			.fingerprint(fingerprint)
+			.identity(client_cert_path, client_key_path, client_pass)
+			.build()
+	}
 
-		Ok(Self {
-			base_url,
-			remote_version: version.version,
-			underlying_http_client: http_client,
-		})
+	/// Connect to any smallstep instance with only the CA fingerprint,
+	/// choosing where the fetched root certificate is kept.
+	///
+	/// `new_from_fingerprint` keeps the root in the default cache directory,
+	/// see `trust::RootCache` for the other places it can be kept. If the cache
+	/// already contains a root matching the fingerprint it is reused without
+	/// fetching it again.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use tinystep::{trust::RootCache, TinystepClient};
+	/// let my_client = TinystepClient::new_from_fingerprint_with_root_cache(
+	///   "https://certs.bluestone.ca.smallstep.com".to_owned(),
+	///   "6cbbfb8bf28e552bc710af1de6c76ed0defcf184e518b466c4a707a824ac410d",
+	///   &RootCache::Ephemeral,
+	/// ).unwrap();
+	/// ```
+	#[instrument]
+	pub fn new_from_fingerprint_with_root_cache(
+		base_url: String,
+		fingerprint: &str,
+		root_cache: &trust::RootCache,
+	) -> Result<Self> {
+		Self::builder()
+			.base_url(base_url)
+			.fingerprint(fingerprint)
+			.root_cache(root_cache.clone())
+			.build()
 	}
 
 	/// Create a client for interacting with a hosted version of smallstep.
@@ -394,22 +510,9 @@ impl TinystepClient {
 	/// ```
 	#[instrument]
 	pub fn new_from_hosted(team_name: &str, specific_authority: Option<String>) -> Result<Self> {
-		let resp = isahc::get(format!(
-			"https://api.smallstep.com/v1/teams/{}/authorities/{}",
-			team_name,
-			specific_authority.unwrap_or_else(|| "ssh".to_owned())
-		))?
-		.json::<types::HostedAuthorityResponse>()?;
-		let root_cert_path =
-			Self::get_root_certificate_from_fingerprint(&resp.url, &resp.fingerprint)?;
-		let http_client = Self::http_client_from_certs(root_cert_path, None)?;
-		let version = Self::get_version(&resp.url, &http_client)?;
-
-		Ok(Self {
-			base_url: resp.url,
-			remote_version: version.version,
-			underlying_http_client: http_client,
-		})
+		Self::builder()
+			.hosted(team_name, specific_authority)
+			.build()
 	}
 
 	/// Create a client for interacting with a hosted version of smallstep, with
@@ -428,7 +531,7 @@ impl TinystepClient {
 	///
 	/// The teamname is the same you go to when you login at:
 	/// `https://smallstep.com/app/${ this is your team name }`
-	#[instrument]
+	#[instrument(skip(client_pass))]
 	pub fn new_from_hosted_with_identity(
 		team_name: &str,
 		specific_authority: Option<String>,
@@ -436,25 +539,10 @@ impl TinystepClient {
 		client_key_path: PathBuf,
 		client_pass: Option<String>,
 	) -> Result<Self> {
-		let resp = isahc::get(format!(
-			"https://api.smallstep.com/v1/teams/{}/authorities/{}",
-			team_name,
-			specific_authority.unwrap_or_else(|| "ssh".to_owned())
-		))?
-		.json::<types::HostedAuthorityResponse>()?;
-		let root_cert_path =
-			Self::get_root_certificate_from_fingerprint(&resp.url, &resp.fingerprint)?;
-		let http_client = Self::http_client_from_certs(
-			root_cert_path,
-			Some((client_cert_path, (client_key_path, client_pass))),
-		)?;
-		let version = Self::get_version(&resp.url, &http_client)?;
-
-		Ok(Self {
-			base_url: resp.url,
-			remote_version: version.version,
-			underlying_http_client: http_client,
-		})
+		Self::builder()
+			.hosted(team_name, specific_authority)
+			.identity(client_cert_path, client_key_path, client_pass)
+			.build()
 	}
 
 	/// Create a specific URL to the smallstep instance.
@@ -485,16 +573,13 @@ impl TinystepClient {
 	///
 	/// To customize the request further you can build the request yourself,
 	/// and use `send_async`.
-	#[instrument]
+	#[instrument(skip(self))]
 	pub async fn delete_async<D>(&self, uri_part: &str) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.delete_async(format!("{}{}", &self.base_url, uri_part))
-			.await?
-			.json::<D>()?)
+		self.execute_async(|| self.empty_request(Method::DELETE, uri_part))
+			.await
 	}
 
 	/// Send a DELETE request to a particular api route.
@@ -503,31 +588,25 @@ impl TinystepClient {
 	/// and use `send`.
 	///
 	/// For async function equivalent see `delete_async`.
-	#[instrument]
+	#[instrument(skip(self))]
 	pub fn delete<D>(&self, uri_part: &str) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.delete(format!("{}{}", &self.base_url, uri_part))?
-			.json::<D>()?)
+		self.execute(|| self.empty_request(Method::DELETE, uri_part))
 	}
 
 	/// Send a GET request asynchronously to a particular api route.
 	///
 	/// To customize the request further you can build the request yourself,
 	/// and use `send_async`.
-	#[instrument]
+	#[instrument(skip(self))]
 	pub async fn get_async<D>(&self, uri_part: &str) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.get_async(format!("{}{}", &self.base_url, uri_part))
-			.await?
-			.json::<D>()?)
+		self.execute_async(|| self.empty_request(Method::GET, uri_part))
+			.await
 	}
 
 	/// Send a GET request to a particular api route.
@@ -536,31 +615,29 @@ impl TinystepClient {
 	/// and use `send`.
 	///
 	/// For async function equivalent see `get_async`.
-	#[instrument]
+	#[instrument(skip(self))]
 	pub fn get<D>(&self, uri_part: &str) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.get(format!("{}{}", &self.base_url, uri_part))?
-			.json::<D>()?)
+		self.execute(|| self.empty_request(Method::GET, uri_part))
 	}
 
 	/// Send a POST request asynchronously to a particular api route.
 	///
 	/// To customize the request further you can build the request yourself,
 	/// and use `send_async`.
-	#[instrument(skip(body))]
+	#[instrument(skip(self, body))]
 	pub async fn post_async<D>(&self, uri_part: &str, body: impl Into<isahc::Body>) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.post_async(format!("{}{}", &self.base_url, uri_part), body)
-			.await?
-			.json::<D>()?)
+		self.before_request_async().await?;
+		error::json_or_error(
+			self.http_client()
+				.post_async(format!("{}{}", &self.base_url, uri_part), body)
+				.await?,
+		)
 	}
 
 	/// Send a POST request to a particular api route.
@@ -569,31 +646,33 @@ impl TinystepClient {
 	/// and use `send`.
 	///
 	/// For async function equivalent see `post_async`.
-	#[instrument(skip(body))]
+	#[instrument(skip(self, body))]
 	pub fn post<D>(&self, uri_part: &str, body: impl Into<isahc::Body>) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.post(format!("{}{}", &self.base_url, uri_part), body)?
-			.json::<D>()?)
+		self.before_request()?;
+		error::json_or_error(
+			self.http_client()
+				.post(format!("{}{}", &self.base_url, uri_part), body)?,
+		)
 	}
 
 	/// Send a PUT request asynchronously to a particular api route.
 	///
 	/// To customize the request further you can build the request yourself,
 	/// and use `send_async`.
-	#[instrument(skip(body))]
+	#[instrument(skip(self, body))]
 	pub async fn put_async<D>(&self, uri_part: &str, body: impl Into<isahc::Body>) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.put_async(format!("{}{}", &self.base_url, uri_part), body)
-			.await?
-			.json::<D>()?)
+		self.before_request_async().await?;
+		error::json_or_error(
+			self.http_client()
+				.put_async(format!("{}{}", &self.base_url, uri_part), body)
+				.await?,
+		)
 	}
 
 	/// Send a PUT request to a particular api route.
@@ -602,15 +681,89 @@ impl TinystepClient {
 	/// and use `send`.
 	///
 	/// For async function equivalent see `put_async`.
-	#[instrument(skip(body))]
+	#[instrument(skip(self, body))]
 	pub fn put<D>(&self, uri_part: &str, body: impl Into<isahc::Body>) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.put(format!("{}{}", &self.base_url, uri_part), body)?
-			.json::<D>()?)
+		self.before_request()?;
+		error::json_or_error(
+			self.http_client()
+				.put(format!("{}{}", &self.base_url, uri_part), body)?,
+		)
+	}
+
+	/// Build a request with a JSON body to a particular api route.
+	fn json_request(
+		&self,
+		method: Method,
+		uri_part: &str,
+		body: Vec<u8>,
+	) -> Result<Request<Vec<u8>>> {
+		Ok(Request::builder()
+			.method(method)
+			.uri(self.construct_url(uri_part))
+			.header("content-type", "application/json")
+			.header("accept", "application/json")
+			.body(body)?)
+	}
+
+	/// Send a POST request asynchronously to a particular api route, with a
+	/// JSON body.
+	///
+	/// For the synchronous equivalent see `post_json`.
+	#[instrument(skip(self, body))]
+	pub async fn post_json_async<S, D>(&self, uri_part: &str, body: &S) -> Result<D>
+	where
+		S: serde::Serialize + ?Sized,
+		D: serde::de::DeserializeOwned,
+	{
+		let body = serde_json::to_vec(body)?;
+		self.execute_async(|| self.json_request(Method::POST, uri_part, body.clone()))
+			.await
+	}
+
+	/// Send a POST request to a particular api route, serializing `body` as
+	/// JSON, and setting the `content-type` for you.
+	///
+	/// For async function equivalent see `post_json_async`.
+	#[instrument(skip(self, body))]
+	pub fn post_json<S, D>(&self, uri_part: &str, body: &S) -> Result<D>
+	where
+		S: serde::Serialize + ?Sized,
+		D: serde::de::DeserializeOwned,
+	{
+		let body = serde_json::to_vec(body)?;
+		self.execute(|| self.json_request(Method::POST, uri_part, body.clone()))
+	}
+
+	/// Send a PUT request asynchronously to a particular api route, with a
+	/// JSON body.
+	///
+	/// For the synchronous equivalent see `put_json`.
+	#[instrument(skip(self, body))]
+	pub async fn put_json_async<S, D>(&self, uri_part: &str, body: &S) -> Result<D>
+	where
+		S: serde::Serialize + ?Sized,
+		D: serde::de::DeserializeOwned,
+	{
+		let body = serde_json::to_vec(body)?;
+		self.execute_async(|| self.json_request(Method::PUT, uri_part, body.clone()))
+			.await
+	}
+
+	/// Send a PUT request to a particular api route, serializing `body` as
+	/// JSON, and setting the `content-type` for you.
+	///
+	/// For async function equivalent see `put_json_async`.
+	#[instrument(skip(self, body))]
+	pub fn put_json<S, D>(&self, uri_part: &str, body: &S) -> Result<D>
+	where
+		S: serde::Serialize + ?Sized,
+		D: serde::de::DeserializeOwned,
+	{
+		let body = serde_json::to_vec(body)?;
+		self.execute(|| self.json_request(Method::PUT, uri_part, body.clone()))
 	}
 
 	/// Send any request asynchronously.
@@ -618,7 +771,7 @@ impl TinystepClient {
 	/// You should use this when wanting to fully customize the request you're
 	/// sending yourself. If you're unsure of the URL to use, you can use:
 	/// `construct_url` in order to get the URL for a particular api route.
-	#[instrument(skip(req))]
+	#[instrument(skip(self, req))]
 	pub async fn send_async<B: Into<isahc::Body>, D>(
 		&self,
 		req: isahc::http::Request<B>,
@@ -626,11 +779,8 @@ impl TinystepClient {
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self
-			.underlying_http_client
-			.send_async(req)
-			.await?
-			.json::<D>()?)
+		self.before_request_async().await?;
+		error::json_or_error(self.http_client().send_async(req).await?)
 	}
 
 	/// Send any request.
@@ -640,12 +790,13 @@ impl TinystepClient {
 	/// `construct_url` in order to get the URL for a particular api route.
 	///
 	/// For an async function equivalent you can use: `send_async`.
-	#[instrument(skip(req))]
+	#[instrument(skip(self, req))]
 	pub fn send<B: Into<isahc::Body>, D>(&self, req: isahc::http::Request<B>) -> Result<D>
 	where
 		D: serde::de::DeserializeOwned,
 	{
-		Ok(self.underlying_http_client.send(req)?.json::<D>()?)
+		self.before_request()?;
+		error::json_or_error(self.http_client().send(req)?)
 	}
 }
 
@@ -653,6 +804,26 @@ impl TinystepClient {
 mod unit_tests {
 	use super::*;
 
+	#[test]
+	pub fn test_json_request() {
+		let client = TinystepClient::builder()
+			.base_url("https://ca.internal")
+			.ca_file(PathBuf::from("/dev/null"))
+			.skip_version_probe(true)
+			.build()
+			.unwrap();
+		let req = client
+			.json_request(
+				Method::POST,
+				"/renew",
+				serde_json::to_vec(&serde_json::json!({"ott": "token"})).unwrap(),
+			)
+			.unwrap();
+		assert_eq!(req.uri(), "https://ca.internal/renew");
+		assert_eq!(req.headers()["content-type"], "application/json");
+		assert_eq!(req.body().as_slice(), br#"{"ott":"token"}"#);
+	}
+
 	#[test]
 	pub fn test_is_send_and_sync() {
 		fn is_send<T: Send>() {}
@@ -660,5 +831,6 @@ mod unit_tests {
 
 		is_send::<TinystepClient>();
 		is_sync::<TinystepClient>();
+		is_send::<types::StepProvisionersAsyncPaginator<'static>>();
 	}
 }
@@ -0,0 +1,272 @@
+//! Retrying requests that failed for reasons that are likely to go away on
+//! their own, like a smallstep instance restarting.
+
+use isahc::{
+	http::{header::RETRY_AFTER, Method, Response, StatusCode},
+	Body,
+};
+use std::{
+	collections::hash_map::RandomState,
+	convert::TryFrom,
+	hash::{BuildHasher, Hasher},
+	time::{Duration, SystemTime},
+};
+
+/// When, and how a `TinystepClient` should retry requests.
+///
+/// Only requests whose body can be replayed are ever retried, which is every
+/// verb except `post`, `put`, and `send` (use `post_json`, or `put_json`
+/// instead). By default only idempotent requests (`GET`, `HEAD`, `PUT`,
+/// `DELETE`, `OPTIONS`) are retried.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::{path::PathBuf, time::Duration};
+/// # use tinystep::{retry::RetryPolicy, TinystepClient};
+/// let my_client = TinystepClient::builder()
+///   .base_url("https://ca.internal")
+///   .ca_file(PathBuf::from("/etc/step/certs/root_ca.crt"))
+///   .retry_policy(RetryPolicy {
+///     max_attempts: 5,
+///     max_backoff: Duration::from_secs(30),
+///     ..RetryPolicy::default()
+///   })
+///   .build()
+///   .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+	/// The maximum number of times to attempt a request, including the first
+	/// attempt. A value of `1` turns retrying off.
+	pub max_attempts: u32,
+	/// How long to wait before the first retry.
+	pub initial_backoff: Duration,
+	/// The most we'll ever wait between attempts, this also caps how long
+	/// we'll honour a `Retry-After` header for.
+	pub max_backoff: Duration,
+	/// How much the backoff grows by after each attempt, this must be at
+	/// least `1.0`.
+	pub multiplier: f64,
+	/// Wait a random amount between zero, and the backoff ("full jitter"),
+	/// so a fleet of clients doesn't retry in lockstep.
+	pub jitter: bool,
+	/// The response statuses that should be retried.
+	pub retry_statuses: Vec<StatusCode>,
+	/// Decides if a transport error should be retried, by default:
+	/// `RetryPolicy::is_transient_transport_error`.
+	pub retry_transport_error: fn(&isahc::Error) -> bool,
+	/// Wait for as long as a `Retry-After` header asks (up to `max_backoff`),
+	/// rather than our own backoff.
+	pub respect_retry_after: bool,
+	/// Also retry requests that aren't idempotent, like `POST`. Only turn this
+	/// on if the requests you're making are safe to repeat.
+	pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: 3,
+			initial_backoff: Duration::from_millis(100),
+			max_backoff: Duration::from_secs(10),
+			multiplier: 2.0,
+			jitter: true,
+			retry_statuses: vec![
+				StatusCode::TOO_MANY_REQUESTS,
+				StatusCode::BAD_GATEWAY,
+				StatusCode::SERVICE_UNAVAILABLE,
+				StatusCode::GATEWAY_TIMEOUT,
+			],
+			retry_transport_error: Self::is_transient_transport_error,
+			respect_retry_after: true,
+			retry_non_idempotent: false,
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// Never retry, this is what a `TinystepClient` uses unless configured
+	/// otherwise.
+	#[must_use]
+	pub fn none() -> Self {
+		Self {
+			max_attempts: 1,
+			..Self::default()
+		}
+	}
+
+	/// The transport errors retried by default, failing to connect, or
+	/// resolve the host, timing out, and the connection closing without a
+	/// response.
+	#[must_use]
+	pub fn is_transient_transport_error(err: &isahc::Error) -> bool {
+		matches!(
+			err,
+			isahc::Error::ConnectFailed
+				| isahc::Error::CouldntResolveHost
+				| isahc::Error::Timeout
+				| isahc::Error::NoResponse
+		)
+	}
+
+	/// How long to wait before retrying a request, or `None` if it shouldn't
+	/// be retried. `attempt` is the attempt that just finished, starting at 1.
+	pub(crate) fn retry_delay(
+		&self,
+		method: &Method,
+		attempt: u32,
+		result: &Result<Response<Body>, isahc::Error>,
+	) -> Option<Duration> {
+		if attempt >= self.max_attempts || !(self.retry_non_idempotent || is_idempotent(method)) {
+			return None;
+		}
+
+		match result {
+			Ok(resp) if self.retry_statuses.contains(&resp.status()) => {
+				let retry_after = if self.respect_retry_after {
+					retry_after(resp)
+				} else {
+					None
+				};
+				Some(
+					retry_after
+						.unwrap_or_else(|| self.backoff(attempt))
+						.min(self.max_backoff),
+				)
+			}
+			Err(err) if (self.retry_transport_error)(err) => Some(self.backoff(attempt)),
+			_ => None,
+		}
+	}
+
+	/// The backoff after a particular attempt, with jitter applied. A backoff
+	/// too large to represent saturates to `max_backoff`.
+	fn backoff(&self, attempt: u32) -> Duration {
+		let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+		let backoff = Duration::try_from_secs_f64(
+			self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent),
+		)
+		.map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+		if self.jitter {
+			random_up_to(backoff)
+		} else {
+			backoff
+		}
+	}
+}
+
+/// If repeating a request with this method is safe.
+fn is_idempotent(method: &Method) -> bool {
+	matches!(
+		*method,
+		Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+	)
+}
+
+/// Read a `Retry-After` header, which is either a number of seconds, or a
+/// date to retry after.
+fn retry_after(resp: &Response<Body>) -> Option<Duration> {
+	let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+	if let Ok(seconds) = value.parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+	let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+	Some(
+		SystemTime::from(at)
+			.duration_since(SystemTime::now())
+			.unwrap_or_default(),
+	)
+}
+
+/// A random duration between zero, and `max`. This doesn't need to be
+/// cryptographically random, just different between clients.
+fn random_up_to(max: Duration) -> Duration {
+	let mut hasher = RandomState::new().build_hasher();
+	hasher.write_u128(
+		SystemTime::now()
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_nanos(),
+	);
+	#[allow(clippy::cast_possible_truncation)]
+	let max_nanos = max.as_nanos().min(u128::from(u64::MAX - 1)) as u64;
+	Duration::from_nanos(hasher.finish() % (max_nanos + 1))
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+
+	fn response(status: u16, retry_after: Option<&str>) -> Result<Response<Body>, isahc::Error> {
+		let mut bldr = Response::builder().status(status);
+		if let Some(retry_after) = retry_after {
+			bldr = bldr.header(RETRY_AFTER, retry_after);
+		}
+		Ok(bldr.body(Body::empty()).unwrap())
+	}
+
+	#[test]
+	pub fn test_retry_delay() {
+		let policy = RetryPolicy {
+			jitter: false,
+			..RetryPolicy::default()
+		};
+
+		assert_eq!(
+			policy.retry_delay(&Method::GET, 1, &response(503, None)),
+			Some(Duration::from_millis(100))
+		);
+		assert_eq!(
+			policy.retry_delay(&Method::GET, 2, &response(503, None)),
+			Some(Duration::from_millis(200))
+		);
+		assert_eq!(
+			policy.retry_delay(&Method::GET, 1, &response(429, Some("3"))),
+			Some(Duration::from_secs(3))
+		);
+		assert_eq!(
+			policy.retry_delay(&Method::GET, 1, &Err(isahc::Error::ConnectFailed)),
+			Some(Duration::from_millis(100))
+		);
+		// Out of attempts, not retryable, or not idempotent.
+		assert_eq!(
+			policy.retry_delay(&Method::GET, 3, &response(503, None)),
+			None
+		);
+		assert_eq!(
+			policy.retry_delay(&Method::GET, 1, &response(401, None)),
+			None
+		);
+		assert_eq!(
+			policy.retry_delay(&Method::POST, 1, &response(503, None)),
+			None
+		);
+		assert_eq!(
+			RetryPolicy::none().retry_delay(&Method::GET, 1, &response(503, None)),
+			None
+		);
+	}
+
+	#[test]
+	pub fn test_backoff_saturates() {
+		let policy = RetryPolicy {
+			max_attempts: u32::MAX,
+			initial_backoff: Duration::from_secs(u64::MAX / 2),
+			max_backoff: Duration::from_secs(60),
+			multiplier: 1e300,
+			jitter: false,
+			..RetryPolicy::default()
+		};
+		assert_eq!(policy.backoff(1), Duration::from_secs(60));
+		assert_eq!(policy.backoff(u32::MAX - 1), Duration::from_secs(60));
+		assert_eq!(
+			RetryPolicy {
+				multiplier: f64::NAN,
+				..policy
+			}
+			.backoff(2),
+			Duration::from_secs(60)
+		);
+	}
+}
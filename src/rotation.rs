@@ -0,0 +1,443 @@
+//! Picking up new root certificates when a smallstep instance rotates its
+//! root.
+//!
+//! Once a client has been constructed it trusts exactly the roots it was given.
+//! `TinystepClient::with_root_rotation` periodically fetches `/roots` over
+//! that trusted channel, and starts trusting any new roots that are signed,
+//! or cross-signed by a root we already trust. Only currently valid
+//! certificate authorities are ever accepted, so a leaf certificate from
+//! `/roots` never ends up trusted. The old roots are kept, so certificates
+//! issued before the rotation keep working.
+
+use crate::{
+	error::{json_or_error, Error, Result},
+	sink::{write_file_atomic, PrivateDirectory},
+	types::StepRootsResponse,
+	TinystepClient,
+};
+use foreign_types::ForeignTypeRef;
+use openssl::{asn1::Asn1Time, x509::X509};
+use std::{
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, Mutex, PoisonError,
+	},
+	time::{Duration, SystemTime},
+};
+use tracing::{debug, info, instrument, warn};
+
+/// A function called with every PEM Encoded root the client trusts, whenever
+/// that changes.
+pub type RootChangeCallback = Arc<dyn Fn(&[String]) + Send + Sync>;
+
+/// Configuration for picking up rotated roots.
+#[derive(Clone)]
+pub struct RootRotation {
+	/// How often to check `/roots` for new roots, defaults to an hour. The
+	/// check happens before a request once this long has passed, so an idle
+	/// client doesn't check.
+	pub check_interval: Duration,
+	/// Called with the new set of trusted roots whenever it changes, e.g. to
+	/// hand them to a `tls::ReloadableTls`.
+	pub on_change: Option<RootChangeCallback>,
+}
+
+impl Default for RootRotation {
+	fn default() -> Self {
+		Self {
+			check_interval: Duration::from_secs(60 * 60),
+			on_change: None,
+		}
+	}
+}
+
+impl std::fmt::Debug for RootRotation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("RootRotation")
+			.field("check_interval", &self.check_interval)
+			.field("on_change", &self.on_change.as_ref().map(|_| "..."))
+			.finish()
+	}
+}
+
+/// The current state of root rotation for a client, shared between all clones
+/// of a client.
+#[derive(Debug)]
+pub(crate) struct RootRotationState {
+	/// How rotation was configured.
+	settings: RootRotation,
+	/// The directory the union of every trusted root is written to, removed
+	/// once every clone of the client is dropped.
+	bundle_dir: PrivateDirectory,
+	/// Every root we currently trust.
+	trusted: Mutex<Vec<X509>>,
+	/// When we should next check for new roots.
+	next_check: Mutex<SystemTime>,
+	/// If a check is currently happening, so we don't check more than once.
+	in_flight: AtomicBool,
+}
+
+impl RootRotationState {
+	/// If it's time to check, and no-one else is checking, claim the check.
+	fn should_check(&self) -> bool {
+		let due = SystemTime::now()
+			>= *self
+				.next_check
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner);
+		due && !self.in_flight.swap(true, Ordering::AcqRel)
+	}
+
+	/// Where the union of every trusted root is written.
+	fn bundle_path(&self) -> PathBuf {
+		self.bundle_dir.path().join("roots.pem")
+	}
+}
+
+/// If two certificates are the same certificate.
+fn same_cert(a: &X509, b: &X509) -> bool {
+	matches!((a.to_der(), b.to_der()), (Ok(a), Ok(b)) if a == b)
+}
+
+/// If `cert` is a certificate authority (`CA:TRUE` in its basic constraints),
+/// and is valid right now.
+fn is_valid_ca(cert: &X509) -> bool {
+	// Safety: the pointer is valid for as long as `cert` is borrowed, and
+	// getting the flags only caches the parsed extensions on the certificate.
+	let flags = unsafe { openssl_sys::X509_get_extension_flags(cert.as_ptr()) };
+	let is_ca = flags & openssl_sys::EXFLAG_CA != 0 && flags & openssl_sys::EXFLAG_INVALID == 0;
+	is_ca
+		&& Asn1Time::days_from_now(0)
+			.map(|now| cert.not_before() <= now && cert.not_after() > now)
+			.unwrap_or(false)
+}
+
+/// If `cert` has a valid signature from any of `trusted`.
+fn signed_by_any(cert: &X509, trusted: &[X509]) -> bool {
+	trusted.iter().any(|root| {
+		root.public_key()
+			.and_then(|key| cert.verify(&key))
+			.unwrap_or(false)
+	})
+}
+
+/// If `cert` is a cross-signed copy of `root`, the same subject, and key, but
+/// a different issuer.
+fn cross_signs(cert: &X509, root: &X509) -> bool {
+	let same_subject = matches!(
+		(cert.subject_name().to_der(), root.subject_name().to_der()),
+		(Ok(a), Ok(b)) if a == b
+	);
+	let same_key = matches!(
+		(cert.public_key(), root.public_key()),
+		(Ok(a), Ok(b)) if a.public_eq(&b)
+	);
+	same_subject && same_key
+}
+
+/// Figure out which of the offered roots we should start trusting. A root is
+/// accepted if it is signed by a root we trust, or if any offered certificate
+/// cross-signs it, and that certificate is signed by a root we trust. Both
+/// the root, and any certificate cross-signing it need to be currently valid
+/// certificate authorities.
+fn accept_new_roots(trusted: &[X509], offered: &[X509]) -> Vec<X509> {
+	let mut trusted = trusted.to_vec();
+	let mut accepted = Vec::new();
+	loop {
+		let newly_accepted = offered
+			.iter()
+			.filter(|root| !trusted.iter().any(|known| same_cert(known, root)))
+			.filter(|root| is_valid_ca(root))
+			.filter(|root| {
+				signed_by_any(root, &trusted)
+					|| offered.iter().any(|cross| {
+						cross_signs(cross, root)
+							&& is_valid_ca(cross) && signed_by_any(cross, &trusted)
+					})
+			})
+			.cloned()
+			.collect::<Vec<_>>();
+		if newly_accepted.is_empty() {
+			return accepted;
+		}
+		trusted.extend(newly_accepted.iter().cloned());
+		accepted.extend(newly_accepted);
+	}
+}
+
+impl TinystepClient {
+	/// Pick up new roots when the smallstep instance rotates its root.
+	///
+	/// Before a request, once `check_interval` has passed, the client fetches
+	/// `/roots`. Any new, currently valid certificate authority signed, or
+	/// cross-signed by a root the client already trusts is added to the
+	/// trusted roots, and the internal http client is atomically replaced so
+	/// every clone of this client trusts it. Roots that can't be tied back to
+	/// a trusted root are ignored with a warning.
+	///
+	/// # Errors
+	///
+	/// - When the trusted roots can't be read.
+	/// - When the directory for the trusted roots can't be created.
+	pub fn with_root_rotation(mut self, rotation: RootRotation) -> Result<Self> {
+		let trusted = X509::stack_from_pem(&std::fs::read(&self.http_settings().ca_path)?)?;
+		if trusted.is_empty() {
+			return Err(Error::Trust("No root certificates present".to_owned()));
+		}
+
+		self.root_rotation = Some(Arc::new(RootRotationState {
+			settings: rotation,
+			bundle_dir: PrivateDirectory::new("tinystep-roots")?,
+			trusted: Mutex::new(trusted),
+			next_check: Mutex::new(SystemTime::now()),
+			in_flight: AtomicBool::new(false),
+		}));
+		Ok(self)
+	}
+
+	/// Check for new roots right now, returning if the trusted roots changed.
+	///
+	/// For an async function equivalent see `refresh_roots_async`.
+	///
+	/// # Errors
+	///
+	/// - When root rotation has not been turned on.
+	/// - When `/roots` can't be fetched.
+	/// - When the new trusted roots can't be written, or loaded.
+	#[instrument]
+	pub fn refresh_roots(&self) -> Result<bool> {
+		self.ensure_root_rotation()?;
+		let resp = json_or_error::<StepRootsResponse>(
+			self.http_client().get(self.construct_url("/roots"))?,
+		)?;
+		self.install_roots(&resp)
+	}
+
+	/// Check for new roots right now asynchronously, returning if the trusted
+	/// roots changed.
+	///
+	/// # Errors
+	///
+	/// - When root rotation has not been turned on.
+	/// - When `/roots` can't be fetched.
+	/// - When the new trusted roots can't be written, or loaded.
+	#[instrument]
+	pub async fn refresh_roots_async(&self) -> Result<bool> {
+		self.ensure_root_rotation()?;
+		let resp = json_or_error::<StepRootsResponse>(
+			self.http_client()
+				.get_async(self.construct_url("/roots"))
+				.await?,
+		)?;
+		self.install_roots(&resp)
+	}
+
+	/// Check for new roots if rotation is turned on, and a check is due. A
+	/// failed check only logs a warning, since the roots we already trust are
+	/// still good.
+	pub(crate) fn refresh_roots_if_needed(&self) {
+		if let Some(state) = self.root_rotation.as_deref() {
+			if state.should_check() {
+				let result = self.refresh_roots();
+				self.finish_root_check(state, result);
+			}
+		}
+	}
+
+	/// Check for new roots asynchronously if rotation is turned on, and a
+	/// check is due.
+	pub(crate) async fn refresh_roots_if_needed_async(&self) {
+		if let Some(state) = self.root_rotation.as_deref() {
+			if state.should_check() {
+				let result = self.refresh_roots_async().await;
+				self.finish_root_check(state, result);
+			}
+		}
+	}
+
+	/// Record that a periodic check finished.
+	fn finish_root_check(&self, state: &RootRotationState, result: Result<bool>) {
+		*state
+			.next_check
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = SystemTime::now() + state.settings.check_interval;
+		state.in_flight.store(false, Ordering::Release);
+		if let Err(err) = result {
+			warn!("Failed to check {} for new roots: {}", self.base_url, err);
+		}
+	}
+
+	/// Get the rotation state, erroring if rotation hasn't been turned on.
+	fn ensure_root_rotation(&self) -> Result<&RootRotationState> {
+		self.root_rotation
+			.as_deref()
+			.ok_or_else(|| Error::Config("Root rotation has not been turned on".to_owned()))
+	}
+
+	/// Trust any acceptable new roots, and swap the http client over to them.
+	fn install_roots(&self, resp: &StepRootsResponse) -> Result<bool> {
+		let state = self.ensure_root_rotation()?;
+		let mut offered = Vec::with_capacity(resp.crts.len());
+		for pem in &resp.crts {
+			offered.extend(X509::stack_from_pem(pem.as_bytes())?);
+		}
+
+		let mut trusted = state.trusted.lock().unwrap_or_else(PoisonError::into_inner);
+		let accepted = accept_new_roots(&trusted, &offered);
+		for root in &offered {
+			let known = trusted
+				.iter()
+				.chain(accepted.iter())
+				.any(|t| same_cert(t, root));
+			if !known
+				&& !offered
+					.iter()
+					.any(|cross| cross_signs(cross, root) && !same_cert(cross, root))
+			{
+				warn!(
+					"Ignoring root: {:?} from {} which isn't signed by a trusted root",
+					root.subject_name(),
+					self.base_url
+				);
+			}
+		}
+		if accepted.is_empty() {
+			debug!("No new roots from: {}", self.base_url);
+			return Ok(false);
+		}
+
+		let mut union = trusted.clone();
+		union.extend(accepted);
+		let pems = union
+			.iter()
+			.map(|root| Ok(String::from_utf8_lossy(&root.to_pem()?).into_owned()))
+			.collect::<Result<Vec<_>>>()?;
+		let bundle_path = state.bundle_path();
+		write_file_atomic(&bundle_path, pems.concat().as_bytes(), 0o600, None, None)?;
+		self.update_http_settings(|settings| settings.ca_path = bundle_path)?;
+		*trusted = union;
+		drop(trusted);
+
+		info!(
+			"Trusted roots for: {} changed, now trusting: {} roots",
+			self.base_url,
+			pems.len()
+		);
+		if let Some(on_change) = &state.settings.on_change {
+			on_change(&pems);
+		}
+		Ok(true)
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use crate::test_util::{self, new_key as key, CertOptions};
+	use openssl::pkey::{PKey, Private};
+
+	/// A certificate authority valid for the next day.
+	fn cert(subject: &str, key: &PKey<Private>, issuer: &str, issuer_key: &PKey<Private>) -> X509 {
+		build_cert(subject, key, issuer, issuer_key, true, (0, 86_400))
+	}
+
+	/// A certificate valid between two offsets from now, in seconds.
+	fn build_cert(
+		subject: &str,
+		key: &PKey<Private>,
+		issuer: &str,
+		issuer_key: &PKey<Private>,
+		ca: bool,
+		validity: (i64, i64),
+	) -> X509 {
+		test_util::cert(
+			subject,
+			key,
+			CertOptions {
+				issuer: Some((issuer, issuer_key)),
+				ca,
+				validity,
+				..CertOptions::default()
+			},
+		)
+	}
+
+	#[test]
+	pub fn test_accept_new_roots() {
+		let (old_key, new_key, other_key) = (key(), key(), key());
+		let old_root = cert("old", &old_key, "old", &old_key);
+		let new_root = cert("new", &new_key, "new", &new_key);
+		let cross_signed = cert("new", &new_key, "old", &old_key);
+		let other_root = cert("other", &other_key, "other", &other_key);
+		let trusted = vec![old_root.clone()];
+
+		// Without the cross-signed certificate there's nothing tying the new
+		// root back to the old one.
+		assert!(accept_new_roots(&trusted, &[old_root.clone(), new_root.clone()]).is_empty());
+
+		let accepted = accept_new_roots(
+			&trusted,
+			&[old_root, new_root.clone(), cross_signed, other_root.clone()],
+		);
+		assert!(accepted.iter().any(|root| same_cert(root, &new_root)));
+		assert!(!accepted.iter().any(|root| same_cert(root, &other_root)));
+
+		// Validly signed by the trusted root, but not something to trust.
+		let leaf = build_cert("leaf", &other_key, "old", &old_key, false, (0, 86_400));
+		let expired = build_cert("expired", &other_key, "old", &old_key, true, (-7200, -3600));
+		let not_yet_valid = build_cert("future", &other_key, "old", &old_key, true, (3600, 7200));
+		assert!(accept_new_roots(&trusted, &[leaf, expired, not_yet_valid]).is_empty());
+
+		// A leaf "cross-signing" a root doesn't tie it back either.
+		let leaf_cross = build_cert("new", &new_key, "old", &old_key, false, (0, 86_400));
+		assert!(accept_new_roots(&trusted, &[new_root, leaf_cross]).is_empty());
+	}
+
+	#[test]
+	pub fn test_install_roots() {
+		let (old_key, new_key) = (key(), key());
+		let old_root = cert("old", &old_key, "old", &old_key);
+		let new_root = cert("new", &new_key, "new", &new_key);
+		let cross_signed = cert("new", &new_key, "old", &old_key);
+		let pem = |cert: &X509| String::from_utf8(cert.to_pem().unwrap()).unwrap();
+
+		let ca_path =
+			std::env::temp_dir().join(format!("tinystep-rotation-{}.pem", std::process::id()));
+		std::fs::write(&ca_path, pem(&old_root)).unwrap();
+		let changes = Arc::new(Mutex::new(Vec::new()));
+		let on_change = changes.clone();
+		let client = TinystepClient::builder()
+			.base_url("http://127.0.0.1:1")
+			.ca_file(ca_path.clone())
+			.root_rotation(RootRotation {
+				on_change: Some(Arc::new(move |pems: &[String]| {
+					on_change.lock().unwrap().push(pems.len());
+				})),
+				..RootRotation::default()
+			})
+			.skip_version_probe(true)
+			.build()
+			.unwrap();
+
+		let resp = StepRootsResponse {
+			crts: vec![pem(&old_root), pem(&new_root), pem(&cross_signed)],
+		};
+		assert!(client.install_roots(&resp).unwrap());
+		let bundle_path = client.root_rotation.as_ref().unwrap().bundle_path();
+		assert_eq!(client.http_settings().ca_path, bundle_path);
+		let bundle = X509::stack_from_pem(&std::fs::read(&bundle_path).unwrap()).unwrap();
+		// The old root, the new root, and the certificate cross-signing it.
+		assert_eq!(bundle.len(), 3);
+		assert!(bundle.iter().any(|root| same_cert(root, &new_root)));
+		assert_eq!(*changes.lock().unwrap(), vec![3]);
+
+		// Nothing new the second time around.
+		assert!(!client.install_roots(&resp).unwrap());
+		assert_eq!(*changes.lock().unwrap(), vec![3]);
+
+		drop(client);
+		assert!(!bundle_path.parent().unwrap().exists());
+		std::fs::remove_file(&ca_path).unwrap();
+	}
+}
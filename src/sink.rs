@@ -0,0 +1,603 @@
+//! Sinks for persisting certificates issued by a smallstep instance.
+//!
+//! When a certificate gets renewed, anything reading it from disk should
+//! either see the old certificate, or the new one. Never half of a PEM file.
+//! `FileSink` handles this by writing every file to a temporary file in the
+//! same directory, and then renaming it over the top of the old file. Once
+//! everything has been written it can run a series of `PostWriteHook`s, such
+//! as running a command or signalling a process to reload.
+
+use crate::{
+	error::{Error, Result},
+	types::StepSignResponse,
+};
+use std::{
+	fs::{DirBuilder, OpenOptions},
+	io::prelude::*,
+	path::{Path, PathBuf},
+	process::Command,
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, instrument};
+
+/// A certificate issued by a smallstep instance, along with everything needed
+/// to use it.
+#[derive(Clone, Debug)]
+pub struct CertificateBundle {
+	/// The PEM Encoded leaf certificate.
+	pub certificate: String,
+	/// The PEM Encoded intermediate certificates that should be served
+	/// alongside the leaf certificate.
+	pub chain: Vec<String>,
+	/// The PEM Encoded private key for the certificate. This is optional since
+	/// renewing a certificate keeps the same key, so there is no need to write
+	/// it out again.
+	pub private_key: Option<String>,
+}
+
+impl From<StepSignResponse> for CertificateBundle {
+	/// Convert a freshly signed, or renewed certificate into a bundle. There
+	/// is no private key since smallstep never sees it.
+	fn from(resp: StepSignResponse) -> Self {
+		let chain = if resp.cert_chain.len() > 1 {
+			resp.cert_chain.into_iter().skip(1).collect()
+		} else {
+			vec![resp.ca]
+		};
+
+		Self {
+			certificate: resp.crt,
+			chain,
+			private_key: None,
+		}
+	}
+}
+
+/// A function to call with the bundle that was just written.
+pub type PostWriteCallback = Arc<dyn Fn(&CertificateBundle) -> Result<()> + Send + Sync>;
+
+/// An action to take once a `FileSink` has successfully written a
+/// `CertificateBundle` to disk.
+#[derive(Clone)]
+pub enum PostWriteHook {
+	/// Run a program with a list of arguments, this is not run through a
+	/// shell. A non-zero exit status is treated as an error.
+	Command {
+		/// The program to run.
+		program: PathBuf,
+		/// The arguments to pass to the program.
+		args: Vec<String>,
+	},
+	/// Send a signal to a particular process, e.g. `SIGHUP` to get a server to
+	/// reload its certificates. The pid must be positive, `kill` treats zero,
+	/// and negative pids as whole groups of processes.
+	#[cfg(unix)]
+	Signal {
+		/// The process to signal.
+		pid: i32,
+		/// The signal to send, e.g. `libc::SIGHUP`.
+		signal: i32,
+	},
+	/// Call a function in process, this is useful for things like handing the
+	/// new certificate to a `tls::ReloadableTls`.
+	Callback(PostWriteCallback),
+}
+
+impl std::fmt::Debug for PostWriteHook {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			PostWriteHook::Command { program, args } => f
+				.debug_struct("Command")
+				.field("program", program)
+				.field("args", args)
+				.finish(),
+			#[cfg(unix)]
+			PostWriteHook::Signal { pid, signal } => f
+				.debug_struct("Signal")
+				.field("pid", pid)
+				.field("signal", signal)
+				.finish(),
+			PostWriteHook::Callback(_) => f.write_str("Callback"),
+		}
+	}
+}
+
+impl PostWriteHook {
+	/// Run this particular hook.
+	fn run(&self, bundle: &CertificateBundle) -> Result<()> {
+		match self {
+			PostWriteHook::Command { program, args } => {
+				let status = Command::new(program).args(args).status()?;
+				if !status.success() {
+					return Err(Error::Hook(format!(
+						"{:?} exited with: {}",
+						program, status
+					)));
+				}
+			}
+			#[cfg(unix)]
+			PostWriteHook::Signal { pid, signal } => {
+				if *pid <= 0 {
+					return Err(Error::Hook(format!(
+						"Refusing to signal pid: {}, it isn't a single process",
+						pid
+					)));
+				}
+				// Safety: kill has no memory safety requirements, it only
+				// reports errors through its return value.
+				if unsafe { libc::kill(*pid, *signal) } != 0 {
+					return Err(Error::Hook(format!(
+						"Failed to send signal: {} to pid: {}: {}",
+						signal,
+						pid,
+						std::io::Error::last_os_error()
+					)));
+				}
+			}
+			PostWriteHook::Callback(callback) => callback(bundle)?,
+		}
+
+		Ok(())
+	}
+}
+
+/// Writes a `CertificateBundle` out to a series of files atomically.
+///
+/// The leaf certificate is written to the certificate path. If a chain path
+/// has been configured the intermediates are written there, otherwise they're
+/// appended to the certificate file (the same layout as `step ca certificate`).
+/// The private key, if present in the bundle, and a key path has been
+/// configured will be written with `0600` permissions.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::path::PathBuf;
+/// # use tinystep::sink::{CertificateBundle, FileSink, PostWriteHook};
+/// # let bundle: CertificateBundle = unimplemented!();
+/// let sink = FileSink::new(PathBuf::from("/etc/nginx/tls/server.crt"))
+///   .with_key_path(PathBuf::from("/etc/nginx/tls/server.key"))
+///   .with_hook(PostWriteHook::Command {
+///     program: PathBuf::from("/usr/sbin/nginx"),
+///     args: vec!["-s".to_owned(), "reload".to_owned()],
+///   });
+/// sink.write(&bundle).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct FileSink {
+	/// Where to write the leaf certificate.
+	cert_path: PathBuf,
+	/// Where to write the intermediates, if not specified they're appended to
+	/// the leaf certificate.
+	chain_path: Option<PathBuf>,
+	/// Where to write the private key.
+	key_path: Option<PathBuf>,
+	/// The permissions to write certificates with.
+	cert_mode: u32,
+	/// The user id that should own all the written files.
+	owner: Option<u32>,
+	/// The group id that should own all the written files.
+	group: Option<u32>,
+	/// Hooks to run after writing everything.
+	hooks: Vec<PostWriteHook>,
+}
+
+impl FileSink {
+	/// Create a sink that writes the certificate, and its chain to a single
+	/// file.
+	#[must_use]
+	pub fn new(cert_path: PathBuf) -> Self {
+		Self {
+			cert_path,
+			chain_path: None,
+			key_path: None,
+			cert_mode: 0o644,
+			owner: None,
+			group: None,
+			hooks: Vec::new(),
+		}
+	}
+
+	/// Write the intermediate certificates to their own file, rather than
+	/// appending them to the leaf certificate.
+	#[must_use]
+	pub fn with_chain_path(mut self, chain_path: PathBuf) -> Self {
+		self.chain_path = Some(chain_path);
+		self
+	}
+
+	/// Write the private key (when present in a bundle) to this path. The key
+	/// is always written with `0600` permissions.
+	#[must_use]
+	pub fn with_key_path(mut self, key_path: PathBuf) -> Self {
+		self.key_path = Some(key_path);
+		self
+	}
+
+	/// Override the permissions certificates are written with, defaults to
+	/// `0644`.
+	#[must_use]
+	pub fn with_cert_mode(mut self, cert_mode: u32) -> Self {
+		self.cert_mode = cert_mode;
+		self
+	}
+
+	/// Change the owning user of all written files.
+	#[must_use]
+	pub fn with_owner(mut self, uid: u32) -> Self {
+		self.owner = Some(uid);
+		self
+	}
+
+	/// Change the owning group of all written files.
+	#[must_use]
+	pub fn with_group(mut self, gid: u32) -> Self {
+		self.group = Some(gid);
+		self
+	}
+
+	/// Add a hook to run after a successful write. Hooks are run in the order
+	/// they were added, stopping at the first failure.
+	#[must_use]
+	pub fn with_hook(mut self, hook: PostWriteHook) -> Self {
+		self.hooks.push(hook);
+		self
+	}
+
+	/// The path the leaf certificate is written too.
+	#[must_use]
+	pub fn cert_path(&self) -> &Path {
+		&self.cert_path
+	}
+
+	/// The path the private key is written too, if any.
+	#[must_use]
+	pub fn key_path(&self) -> Option<&Path> {
+		self.key_path.as_deref()
+	}
+
+	/// Write a bundle out to disk, and then run all the post write hooks.
+	///
+	/// The key is written first, then the chain, and finally the certificate
+	/// so anyone watching the certificate file will see a complete set of
+	/// files once it changes.
+	///
+	/// # Errors
+	///
+	/// - When any file fails to be written, or have its ownership changed.
+	/// - When any post write hook fails.
+	#[instrument(skip(bundle))]
+	pub fn write(&self, bundle: &CertificateBundle) -> Result<()> {
+		if let (Some(key_path), Some(key)) = (&self.key_path, &bundle.private_key) {
+			self.write_atomic(key_path, key.as_bytes(), 0o600)?;
+		}
+
+		let mut cert_contents = ensure_trailing_newline(&bundle.certificate);
+		if let Some(chain_path) = &self.chain_path {
+			let chain = bundle
+				.chain
+				.iter()
+				.map(|pem| ensure_trailing_newline(pem))
+				.collect::<String>();
+			self.write_atomic(chain_path, chain.as_bytes(), self.cert_mode)?;
+		} else {
+			for pem in &bundle.chain {
+				cert_contents.push_str(&ensure_trailing_newline(pem));
+			}
+		}
+		self.write_atomic(&self.cert_path, cert_contents.as_bytes(), self.cert_mode)?;
+
+		for hook in &self.hooks {
+			debug!("Running post write hook: {:?}", hook);
+			hook.run(bundle)?;
+		}
+
+		Ok(())
+	}
+
+	/// Write a single file owned by the configured user, and group.
+	fn write_atomic(&self, path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+		write_file_atomic(path, contents, mode, self.owner, self.group)
+	}
+}
+
+/// Write a single file by writing to a temporary file in the same directory,
+/// and renaming it into place. Readers will either see the old file, or the
+/// new one, never a partially written file.
+pub(crate) fn write_file_atomic(
+	path: &Path,
+	contents: &[u8],
+	mode: u32,
+	owner: Option<u32>,
+	group: Option<u32>,
+) -> Result<()> {
+	let file_name = path
+		.file_name()
+		.ok_or_else(|| Error::Config(format!("Path: {:?} is not a file", path)))?
+		.to_string_lossy();
+	let parent = match path.parent() {
+		Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+		_ => PathBuf::from("."),
+	};
+	let nanos = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|dur| dur.subsec_nanos())
+		.unwrap_or_default();
+	let tmp_path = parent.join(format!(
+		".{}.tmp-{}-{}",
+		file_name,
+		std::process::id(),
+		nanos
+	));
+
+	let result = write_and_rename(&tmp_path, path, contents, mode, owner, group);
+	if result.is_err() {
+		let _ = std::fs::remove_file(&tmp_path);
+	}
+	result?;
+
+	// Make sure the rename itself is durable. Windows can't open a directory
+	// as a file, and doesn't need it.
+	#[cfg(unix)]
+	std::fs::File::open(&parent)?.sync_all()?;
+	Ok(())
+}
+
+/// A directory in the temporary directory only we can read, which is removed
+/// along with everything in it once dropped. The name is random, and an
+/// existing directory is never reused, so nothing else can have been put in
+/// it first.
+#[derive(Debug)]
+pub(crate) struct PrivateDirectory(PathBuf);
+
+impl PrivateDirectory {
+	/// Create a new private directory, named with a prefix to tell what it's
+	/// used for.
+	pub(crate) fn new(prefix: &str) -> Result<Self> {
+		let mut random = [0_u8; 16];
+		openssl::rand::rand_bytes(&mut random)?;
+		let path = std::env::temp_dir().join(format!("{}-{}", prefix, hex::encode(random)));
+
+		let mut builder = DirBuilder::new();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::DirBuilderExt;
+			builder.mode(0o700);
+		}
+		builder.create(&path)?;
+		Ok(Self(path))
+	}
+
+	/// The path to the directory.
+	pub(crate) fn path(&self) -> &Path {
+		&self.0
+	}
+}
+
+impl Drop for PrivateDirectory {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+/// Create the temporary file with the correct permissions, fill it, and
+/// move it over the final destination.
+fn write_and_rename(
+	tmp_path: &Path,
+	path: &Path,
+	contents: &[u8],
+	mode: u32,
+	owner: Option<u32>,
+	group: Option<u32>,
+) -> Result<()> {
+	let mut options = OpenOptions::new();
+	options.create_new(true).write(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(mode);
+	}
+
+	let mut fd = options.open(tmp_path)?;
+	fd.write_all(contents)?;
+	fd.sync_all()?;
+	drop(fd);
+
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		// The mode passed to open is filtered by the umask.
+		std::fs::set_permissions(tmp_path, std::fs::Permissions::from_mode(mode))?;
+		if owner.is_some() || group.is_some() {
+			std::os::unix::fs::chown(tmp_path, owner, group)?;
+		}
+	}
+	#[cfg(not(unix))]
+	let _ = (mode, owner, group);
+
+	std::fs::rename(tmp_path, path)?;
+	Ok(())
+}
+
+/// PEM Files are expected to end in a newline, ensure they do so they can
+/// be safely concatenated.
+fn ensure_trailing_newline(pem: &str) -> String {
+	let mut owned = pem.to_owned();
+	if !owned.ends_with('\n') {
+		owned.push('\n');
+	}
+	owned
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+
+	#[test]
+	pub fn test_write_bundle() {
+		let dir = std::env::temp_dir().join(format!("tinystep-sink-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let sink = FileSink::new(dir.join("cert.pem")).with_key_path(dir.join("key.pem"));
+		let bundle = CertificateBundle {
+			certificate: "leaf".to_owned(),
+			chain: vec!["intermediate\n".to_owned()],
+			private_key: Some("key".to_owned()),
+		};
+		sink.write(&bundle).unwrap();
+
+		assert_eq!(
+			std::fs::read_to_string(dir.join("cert.pem")).unwrap(),
+			"leaf\nintermediate\n"
+		);
+		assert_eq!(std::fs::read_to_string(dir.join("key.pem")).unwrap(), "key");
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(dir.join("key.pem"))
+				.unwrap()
+				.permissions()
+				.mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+		// No temporary files should be left behind.
+		assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	pub fn test_write_chain_path() {
+		let dir = std::env::temp_dir().join(format!("tinystep-sink-chain-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let sink = FileSink::new(dir.join("cert.pem")).with_chain_path(dir.join("chain.pem"));
+		let bundle = CertificateBundle {
+			certificate: "leaf\n".to_owned(),
+			chain: vec!["one".to_owned(), "two\n".to_owned()],
+			private_key: Some("key".to_owned()),
+		};
+		sink.write(&bundle).unwrap();
+
+		assert_eq!(
+			std::fs::read_to_string(dir.join("cert.pem")).unwrap(),
+			"leaf\n"
+		);
+		assert_eq!(
+			std::fs::read_to_string(dir.join("chain.pem")).unwrap(),
+			"one\ntwo\n"
+		);
+		// Without a key path the key is never written.
+		assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	pub fn test_failed_write_removes_temporary_file() {
+		let dir = std::env::temp_dir().join(format!("tinystep-sink-fail-{}", std::process::id()));
+		// A non-empty directory can't be renamed over, so the write fails after
+		// the temporary file has been written.
+		std::fs::create_dir_all(dir.join("cert.pem").join("in-the-way")).unwrap();
+
+		let called = Arc::new(std::sync::atomic::AtomicBool::new(false));
+		let hook_called = called.clone();
+		let sink = FileSink::new(dir.join("cert.pem")).with_hook(PostWriteHook::Callback(
+			Arc::new(move |_| {
+				hook_called.store(true, std::sync::atomic::Ordering::SeqCst);
+				Ok(())
+			}),
+		));
+		let bundle = CertificateBundle {
+			certificate: "leaf".to_owned(),
+			chain: Vec::new(),
+			private_key: None,
+		};
+		assert!(sink.write(&bundle).is_err());
+
+		let left = std::fs::read_dir(&dir)
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name())
+			.collect::<Vec<_>>();
+		assert_eq!(left, vec![std::ffi::OsString::from("cert.pem")]);
+		assert!(!called.load(std::sync::atomic::Ordering::SeqCst));
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	pub fn test_hooks() {
+		let dir = std::env::temp_dir().join(format!("tinystep-sink-hooks-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let bundle = CertificateBundle {
+			certificate: "leaf".to_owned(),
+			chain: Vec::new(),
+			private_key: None,
+		};
+
+		let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+		let record = |seen: &Arc<std::sync::Mutex<Vec<String>>>, result: Result<()>| {
+			let seen = seen.clone();
+			let result = std::sync::Mutex::new(Some(result));
+			PostWriteHook::Callback(Arc::new(move |bundle: &CertificateBundle| {
+				seen.lock().unwrap().push(bundle.certificate.clone());
+				result.lock().unwrap().take().unwrap_or(Ok(()))
+			}))
+		};
+
+		// Hooks run in order, stopping at the first failure.
+		let sink = FileSink::new(dir.join("cert.pem"))
+			.with_hook(record(&seen, Ok(())))
+			.with_hook(record(&seen, Err(Error::Hook("nope".to_owned()))))
+			.with_hook(record(&seen, Ok(())));
+		assert!(matches!(sink.write(&bundle), Err(Error::Hook(_))));
+		assert_eq!(*seen.lock().unwrap(), vec!["leaf", "leaf"]);
+
+		#[cfg(unix)]
+		{
+			use std::convert::TryFrom;
+
+			let command = |program: &str| PostWriteHook::Command {
+				program: PathBuf::from(program),
+				args: vec!["ignored".to_owned()],
+			};
+			assert!(FileSink::new(dir.join("cert.pem"))
+				.with_hook(command("true"))
+				.write(&bundle)
+				.is_ok());
+			assert!(matches!(
+				FileSink::new(dir.join("cert.pem"))
+					.with_hook(command("false"))
+					.write(&bundle),
+				Err(Error::Hook(_))
+			));
+			assert!(FileSink::new(dir.join("cert.pem"))
+				.with_hook(command("/nonexistent/tinystep-hook"))
+				.write(&bundle)
+				.is_err());
+
+			for pid in &[0, -1] {
+				assert!(matches!(
+					FileSink::new(dir.join("cert.pem"))
+						.with_hook(PostWriteHook::Signal {
+							pid: *pid,
+							signal: 0,
+						})
+						.write(&bundle),
+					Err(Error::Hook(_))
+				));
+			}
+			// Signal zero only checks the process exists.
+			assert!(FileSink::new(dir.join("cert.pem"))
+				.with_hook(PostWriteHook::Signal {
+					pid: i32::try_from(std::process::id()).unwrap(),
+					signal: 0,
+				})
+				.write(&bundle)
+				.is_ok());
+		}
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,481 @@
+//! Reading the configuration written by the step cli, so a machine that has
+//! already been set up with `step ca bootstrap` doesn't need to be configured
+//! twice.
+//!
+//! The step cli keeps everything under `$STEPPATH` (`~/.step` by default). A
+//! plain bootstrap writes `config/defaults.json`, while a bootstrap with
+//! `--context` writes a `contexts.json` file naming each context, and keeps
+//! the configuration for each under `authorities/<authority>`, with any
+//! per-profile overrides under `profiles/<profile>`.
+//!
+//! `bootstrap` writes the same layout, so machines set up from rust can use
+//! the step cli too.
+
+use crate::{
+	builder::TinystepClientBuilder,
+	error::{Error, Result},
+	sink::write_file_atomic,
+	trust::fetch_root_certificate,
+	TinystepClient,
+};
+use isahc::http::Uri;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+use tracing::{debug, instrument};
+
+/// The `defaults.json` configuration written by the step cli, only the fields
+/// we care about are read.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct StepDefaults {
+	/// The URL of the smallstep instance.
+	#[serde(rename = "ca-url", skip_serializing_if = "Option::is_none")]
+	pub ca_url: Option<String>,
+	/// The fingerprint of the root certificate authority.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub fingerprint: Option<String>,
+	/// The path to the root certificate authority.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub root: Option<PathBuf>,
+}
+
+impl StepDefaults {
+	/// Fill in anything we don't have from another set of defaults.
+	fn or(self, other: Self) -> Self {
+		Self {
+			ca_url: self.ca_url.or(other.ca_url),
+			fingerprint: self.fingerprint.or(other.fingerprint),
+			root: self.root.or(other.root),
+		}
+	}
+}
+
+/// A single context from `contexts.json`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepContext {
+	/// The authority this context points at, the name of the directory
+	/// under `authorities`.
+	pub authority: String,
+	/// The profile this context uses, the name of the directory under
+	/// `profiles`.
+	pub profile: String,
+}
+
+/// The `current-context.json` file written by `step context select`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CurrentContext {
+	/// The name of the currently selected context.
+	context: String,
+}
+
+/// The resolved step cli configuration for a single smallstep instance.
+#[derive(Clone, Debug)]
+pub struct StepConfig {
+	/// The URL of the smallstep instance.
+	pub ca_url: String,
+	/// The fingerprint of the root certificate authority, if known.
+	pub fingerprint: Option<String>,
+	/// The path to the root certificate authority, if known.
+	pub root: Option<PathBuf>,
+	/// The context this configuration was read from, if contexts are in use.
+	pub context: Option<String>,
+}
+
+impl StepConfig {
+	/// The directory the step cli keeps its configuration in, `$STEPPATH` if
+	/// set, otherwise `~/.step`.
+	///
+	/// # Errors
+	///
+	/// - When `$STEPPATH` is not set, and there is no home directory.
+	pub fn step_path() -> Result<PathBuf> {
+		if let Some(step_path) = std::env::var_os("STEPPATH") {
+			return Ok(PathBuf::from(step_path));
+		}
+		std::env::var_os("HOME")
+			.map(|home| PathBuf::from(home).join(".step"))
+			.ok_or_else(|| Error::Config("Neither $STEPPATH nor $HOME are set".to_owned()))
+	}
+
+	/// Load the step cli configuration from `$STEPPATH`.
+	///
+	/// If `context` is not provided, and the step cli is using contexts the
+	/// currently selected context is used.
+	///
+	/// # Errors
+	///
+	/// - When the step path can't be found.
+	/// - See: `StepConfig::load_from`.
+	pub fn load(context: Option<&str>) -> Result<Self> {
+		Self::load_from(&Self::step_path()?, context)
+	}
+
+	/// Load the step cli configuration from a particular step path.
+	///
+	/// # Errors
+	///
+	/// - When a context is requested, but the step cli isn't using contexts.
+	/// - When the context does not exist, or no context is selected.
+	/// - When any of the configuration files can't be read or parsed.
+	/// - When the configuration has no `ca-url`.
+	/// - When the configuration has neither a `root`, nor a `fingerprint`.
+	#[instrument]
+	pub fn load_from(step_path: &Path, context: Option<&str>) -> Result<Self> {
+		let contexts_path = step_path.join("contexts.json");
+		let (base_path, defaults, context) = if contexts_path.exists() {
+			let contexts: HashMap<String, StepContext> =
+				serde_json::from_slice(&std::fs::read(&contexts_path)?)?;
+			let name = if let Some(name) = context {
+				name.to_owned()
+			} else {
+				let current: CurrentContext = serde_json::from_slice(&std::fs::read(
+					step_path.join("current-context.json"),
+				)?)?;
+				current.context
+			};
+			let ctx = contexts
+				.get(&name)
+				.ok_or_else(|| Error::Config(format!("No step context named: {}", name)))?;
+			debug!("Using step context: {} ({:?})", name, ctx);
+
+			let authority_path = step_path.join("authorities").join(&ctx.authority);
+			let profile_defaults = read_defaults(
+				&step_path
+					.join("profiles")
+					.join(&ctx.profile)
+					.join("config")
+					.join("defaults.json"),
+			)?
+			.unwrap_or_default();
+			let authority_defaults = read_defaults(
+				&authority_path.join("config").join("defaults.json"),
+			)?
+			.ok_or_else(|| {
+				Error::Config(format!(
+					"No configuration for step authority: {}",
+					ctx.authority
+				))
+			})?;
+			(
+				authority_path,
+				profile_defaults.or(authority_defaults),
+				Some(name),
+			)
+		} else {
+			if let Some(name) = context {
+				return Err(Error::Config(format!(
+					"Step context: {} was requested, but {:?} has no contexts",
+					name, step_path
+				)));
+			}
+			let defaults = read_defaults(&step_path.join("config").join("defaults.json"))?
+				.ok_or_else(|| {
+					Error::Config(format!("No step configuration found in: {:?}", step_path))
+				})?;
+			(step_path.to_owned(), defaults, None)
+		};
+
+		let ca_url = defaults
+			.ca_url
+			.ok_or_else(|| Error::Config("Step configuration is missing a `ca-url`".to_owned()))?;
+		let root = defaults.root.map(|root| {
+			if root.is_relative() {
+				base_path.join(root)
+			} else {
+				root
+			}
+		});
+		if root.is_none() && defaults.fingerprint.is_none() {
+			return Err(Error::Config(
+				"Step configuration has neither a `root`, nor a `fingerprint`".to_owned(),
+			));
+		}
+
+		Ok(Self {
+			ca_url,
+			fingerprint: defaults.fingerprint,
+			root,
+			context,
+		})
+	}
+}
+
+/// Bootstrap `$STEPPATH` for a smallstep instance, the equivalent of
+/// `step ca bootstrap --ca-url <ca_url> --fingerprint <fingerprint>`.
+///
+/// The root certificate is fetched, and verified against the fingerprint
+/// before anything is written. Without a context `certs/root_ca.crt`, and
+/// `config/defaults.json` are written directly under `$STEPPATH`. With a
+/// context they're written under `authorities/<ca host>`, an empty profile
+/// named after the context is created, and the context is added to
+/// `contexts.json` and selected, just like `step ca bootstrap --context`.
+///
+/// Returns the configuration that was written, which can be passed to
+/// `TinystepClientBuilder::step_config`.
+///
+/// # Errors
+///
+/// - When the step path can't be found.
+/// - When the root can't be fetched, or doesn't match the fingerprint.
+/// - When any of the configuration can't be written.
+#[instrument]
+pub fn bootstrap(ca_url: &str, fingerprint: &str, context: Option<&str>) -> Result<StepConfig> {
+	bootstrap_in(&StepConfig::step_path()?, ca_url, fingerprint, context)
+}
+
+/// Bootstrap a particular step path for a smallstep instance, see:
+/// `bootstrap`.
+///
+/// # Errors
+///
+/// - When the root can't be fetched, or doesn't match the fingerprint.
+/// - When any of the configuration can't be written.
+#[instrument]
+pub fn bootstrap_in(
+	step_path: &Path,
+	ca_url: &str,
+	fingerprint: &str,
+	context: Option<&str>,
+) -> Result<StepConfig> {
+	let ca_url = ca_url.trim_end_matches('/');
+	let root = fetch_root_certificate(ca_url, fingerprint)?;
+	write_bootstrap(step_path, ca_url, fingerprint, &root, context)
+}
+
+/// Write out the step cli configuration for an already verified root.
+fn write_bootstrap(
+	step_path: &Path,
+	ca_url: &str,
+	fingerprint: &str,
+	root_pem: &str,
+	context: Option<&str>,
+) -> Result<StepConfig> {
+	let base_path = if let Some(name) = context {
+		let authority = ca_url
+			.parse::<Uri>()
+			.map_err(|err| Error::Config(format!("Invalid CA URL: {}: {}", ca_url, err)))?
+			.host()
+			.ok_or_else(|| Error::Config(format!("CA URL: {} has no host", ca_url)))?
+			.to_owned();
+		write_contexts(step_path, name, &authority)?;
+
+		let profile_config = step_path.join("profiles").join(name).join("config");
+		std::fs::create_dir_all(&profile_config)?;
+		let profile_defaults = profile_config.join("defaults.json");
+		if !profile_defaults.exists() {
+			write_file_atomic(&profile_defaults, b"{}\n", 0o644, None, None)?;
+		}
+		step_path.join("authorities").join(authority)
+	} else {
+		step_path.to_owned()
+	};
+
+	let certs = base_path.join("certs");
+	let config = base_path.join("config");
+	std::fs::create_dir_all(&certs)?;
+	std::fs::create_dir_all(&config)?;
+	let root_path = certs.join("root_ca.crt");
+	write_file_atomic(&root_path, root_pem.as_bytes(), 0o644, None, None)?;
+	let defaults = StepDefaults {
+		ca_url: Some(ca_url.to_owned()),
+		fingerprint: Some(fingerprint.to_lowercase()),
+		root: Some(root_path.clone()),
+	};
+	let mut serialized = serde_json::to_vec_pretty(&defaults)?;
+	serialized.push(b'\n');
+	write_file_atomic(
+		&config.join("defaults.json"),
+		&serialized,
+		0o644,
+		None,
+		None,
+	)?;
+	debug!("Bootstrapped step configuration in: {:?}", base_path);
+
+	Ok(StepConfig {
+		ca_url: ca_url.to_owned(),
+		fingerprint: defaults.fingerprint,
+		root: Some(root_path),
+		context: context.map(ToOwned::to_owned),
+	})
+}
+
+/// Add (or replace) a context in `contexts.json`, and select it. Any other
+/// contexts, or fields we don't know about are kept as is.
+fn write_contexts(step_path: &Path, name: &str, authority: &str) -> Result<()> {
+	let contexts_path = step_path.join("contexts.json");
+	let mut contexts: JsonMap<String, JsonValue> = match std::fs::read(&contexts_path) {
+		Ok(contents) => serde_json::from_slice(&contents)?,
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => JsonMap::new(),
+		Err(err) => return Err(err.into()),
+	};
+	contexts.insert(
+		name.to_owned(),
+		serde_json::to_value(StepContext {
+			authority: authority.to_owned(),
+			profile: name.to_owned(),
+		})?,
+	);
+
+	std::fs::create_dir_all(step_path)?;
+	write_file_atomic(
+		&contexts_path,
+		&serde_json::to_vec_pretty(&contexts)?,
+		0o644,
+		None,
+		None,
+	)?;
+	write_file_atomic(
+		&step_path.join("current-context.json"),
+		&serde_json::to_vec(&CurrentContext {
+			context: name.to_owned(),
+		})?,
+		0o644,
+		None,
+		None,
+	)
+}
+
+/// Read a `defaults.json` file, returning `None` if it doesn't exist.
+fn read_defaults(path: &Path) -> Result<Option<StepDefaults>> {
+	match std::fs::read(path) {
+		Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+		Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(err.into()),
+	}
+}
+
+impl TinystepClientBuilder {
+	/// Point the builder at the smallstep instance described by a step cli
+	/// configuration. The root file is trusted if there is one, otherwise the
+	/// root is fetched by its fingerprint.
+	#[must_use]
+	pub fn step_config(self, config: &StepConfig) -> Self {
+		let bldr = self.base_url(config.ca_url.clone());
+		match (&config.root, &config.fingerprint) {
+			(Some(root), _) => bldr.ca_file(root.clone()),
+			(None, Some(fingerprint)) => bldr.fingerprint(fingerprint.clone()),
+			(None, None) => bldr,
+		}
+	}
+}
+
+impl TinystepClient {
+	/// Connect to the smallstep instance the step cli has been bootstrapped
+	/// against, reading the configuration from `$STEPPATH`.
+	///
+	/// When the step cli is using contexts (`step ca bootstrap --context`), the
+	/// currently selected context is used unless `context` is provided.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use tinystep::TinystepClient;
+	/// // Whatever `step context select` last chose.
+	/// let my_client = TinystepClient::from_step_config(None).unwrap();
+	/// // A particular context.
+	/// let other_client = TinystepClient::from_step_config(Some("staging")).unwrap();
+	/// ```
+	///
+	/// # Errors
+	///
+	/// - When the step cli configuration can't be read, see: `StepConfig::load`.
+	/// - When connecting to the smallstep instance fails.
+	#[instrument]
+	pub fn from_step_config(context: Option<&str>) -> Result<Self> {
+		let config = StepConfig::load(context)?;
+		Self::builder().step_config(&config).build()
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+
+	#[test]
+	pub fn test_load_context() {
+		let step_path = std::env::temp_dir().join(format!("tinystep-step-{}", std::process::id()));
+		let authority = step_path.join("authorities").join("ca.internal");
+		std::fs::create_dir_all(authority.join("config")).unwrap();
+		std::fs::create_dir_all(step_path.join("profiles").join("work").join("config")).unwrap();
+		std::fs::write(
+			step_path.join("contexts.json"),
+			r#"{"work": {"authority": "ca.internal", "profile": "work"}}"#,
+		)
+		.unwrap();
+		std::fs::write(
+			step_path.join("current-context.json"),
+			r#"{"context": "work"}"#,
+		)
+		.unwrap();
+		std::fs::write(
+			authority.join("config").join("defaults.json"),
+			r#"{"ca-url": "https://ca.internal", "fingerprint": "abc", "root": "certs/root_ca.crt"}"#,
+		)
+		.unwrap();
+		std::fs::write(
+			step_path
+				.join("profiles")
+				.join("work")
+				.join("config")
+				.join("defaults.json"),
+			r#"{"ca-url": "https://ca.internal:9000"}"#,
+		)
+		.unwrap();
+
+		let config = StepConfig::load_from(&step_path, None).unwrap();
+		assert_eq!(config.ca_url, "https://ca.internal:9000");
+		assert_eq!(config.fingerprint.as_deref(), Some("abc"));
+		assert_eq!(
+			config.root,
+			Some(authority.join("certs").join("root_ca.crt"))
+		);
+		assert_eq!(config.context.as_deref(), Some("work"));
+		assert!(StepConfig::load_from(&step_path, Some("missing")).is_err());
+
+		std::fs::remove_dir_all(&step_path).unwrap();
+	}
+
+	#[test]
+	pub fn test_bootstrap_round_trip() {
+		let step_path =
+			std::env::temp_dir().join(format!("tinystep-bootstrap-{}", std::process::id()));
+		let root = "-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n";
+
+		write_bootstrap(&step_path, "https://ca.internal", "ABC", root, None).unwrap();
+		let config = StepConfig::load_from(&step_path, None).unwrap();
+		assert_eq!(config.ca_url, "https://ca.internal");
+		assert_eq!(config.fingerprint.as_deref(), Some("abc"));
+		assert_eq!(
+			std::fs::read_to_string(step_path.join("certs").join("root_ca.crt")).unwrap(),
+			root
+		);
+
+		write_bootstrap(
+			&step_path,
+			"https://ca.internal:9000",
+			"def",
+			root,
+			Some("work"),
+		)
+		.unwrap();
+		let config = StepConfig::load_from(&step_path, None).unwrap();
+		assert_eq!(config.ca_url, "https://ca.internal:9000");
+		assert_eq!(config.context.as_deref(), Some("work"));
+		assert_eq!(
+			config.root,
+			Some(
+				step_path
+					.join("authorities")
+					.join("ca.internal")
+					.join("certs")
+					.join("root_ca.crt")
+			)
+		);
+
+		std::fs::remove_dir_all(&step_path).unwrap();
+	}
+}
@@ -0,0 +1,140 @@
+//! Fixtures shared between the unit tests of each module, certificates, and a
+//! stand in for a smallstep instance.
+
+use openssl::{
+	asn1::Asn1Time,
+	bn::{BigNum, MsbOption},
+	ec::{EcGroup, EcKey},
+	hash::MessageDigest,
+	nid::Nid,
+	pkey::{PKey, Private},
+	x509::{
+		extension::{BasicConstraints, SubjectAlternativeName},
+		X509Name, X509NameBuilder, X509,
+	},
+};
+use std::{
+	convert::TryFrom,
+	io::{BufRead, BufReader, Write},
+	net::TcpListener,
+	sync::mpsc::{channel, Receiver},
+	time::SystemTime,
+};
+
+/// A new P-256 key.
+pub fn new_key() -> PKey<Private> {
+	let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+	PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+}
+
+/// A name with only a common name.
+pub fn name(cn: &str) -> X509Name {
+	let mut name = X509NameBuilder::new().unwrap();
+	name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+	name.build()
+}
+
+/// How `cert` should build a certificate, by default a self-signed
+/// certificate authority valid for the next day.
+pub struct CertOptions<'a> {
+	/// The name, and key of the issuer, or `None` to self-sign.
+	pub issuer: Option<(&'a str, &'a PKey<Private>)>,
+	/// If the certificate is a certificate authority.
+	pub ca: bool,
+	/// When the certificate is valid from, and until in seconds from now.
+	pub validity: (i64, i64),
+	/// A DNS name to add as a subject alternative name.
+	pub dns_name: Option<&'a str>,
+}
+
+impl Default for CertOptions<'_> {
+	fn default() -> Self {
+		Self {
+			issuer: None,
+			ca: true,
+			validity: (0, 86_400),
+			dns_name: None,
+		}
+	}
+}
+
+/// Build, and sign a certificate for `key`.
+pub fn cert(subject: &str, key: &PKey<Private>, options: CertOptions<'_>) -> X509 {
+	let now = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+	let at = |offset: i64| Asn1Time::from_unix(i64::try_from(now).unwrap() + offset).unwrap();
+	let mut serial = BigNum::new().unwrap();
+	serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+	let (issuer, issuer_key) = options.issuer.unwrap_or((subject, key));
+
+	let mut bldr = X509::builder().unwrap();
+	bldr.set_version(2).unwrap();
+	bldr.set_serial_number(&serial.to_asn1_integer().unwrap())
+		.unwrap();
+	bldr.set_subject_name(&name(subject)).unwrap();
+	bldr.set_issuer_name(&name(issuer)).unwrap();
+	bldr.set_pubkey(key).unwrap();
+	bldr.set_not_before(&at(options.validity.0)).unwrap();
+	bldr.set_not_after(&at(options.validity.1)).unwrap();
+	if options.ca {
+		bldr.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+			.unwrap();
+	}
+	if let Some(dns_name) = options.dns_name {
+		let san = SubjectAlternativeName::new()
+			.dns(dns_name)
+			.build(&bldr.x509v3_context(None, None))
+			.unwrap();
+		bldr.append_extension(san).unwrap();
+	}
+	bldr.sign(issuer_key, MessageDigest::sha256()).unwrap();
+	bldr.build()
+}
+
+/// A PEM Encoded self-signed certificate authority valid for the next day.
+pub fn root_pem(subject: &str) -> String {
+	let pem = cert(subject, &new_key(), CertOptions::default())
+		.to_pem()
+		.unwrap();
+	String::from_utf8(pem).unwrap()
+}
+
+/// Serve plain http on localhost until the test process exits, answering
+/// every request with the status, and JSON body `respond` gives for its
+/// request line. Returns the base url, and every request (the request line,
+/// and headers) as it's received.
+pub fn serve(
+	respond: impl Fn(&str) -> (u16, String) + Send + 'static,
+) -> (String, Receiver<String>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let base_url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+	let (requests, received) = channel();
+	std::thread::spawn(move || {
+		for sock in listener.incoming() {
+			let mut sock = sock.unwrap();
+			let mut reader = BufReader::new(sock.try_clone().unwrap());
+			let mut request = String::new();
+			loop {
+				let mut line = String::new();
+				if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+					break;
+				}
+				request.push_str(&line);
+			}
+
+			let (status, body) = respond(request.lines().next().unwrap_or_default());
+			let _ = requests.send(request);
+			write!(
+				sock,
+				"HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+				status,
+				body.len(),
+				body
+			)
+			.unwrap();
+		}
+	});
+	(base_url, received)
+}
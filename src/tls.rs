@@ -0,0 +1,425 @@
+//! Hot reloading OpenSSL configuration backed by smallstep issued
+//! certificates.
+//!
+//! Certificates issued by smallstep are generally short lived, which means
+//! servers, and clients using them need to pick up renewed certificates
+//! without restarting. `ReloadableTls` wraps an `SslAcceptor`, and an
+//! `SslConnector` whose identity, and trusted roots can be swapped out at any
+//! point. Connections that have already been established keep using the
+//! certificate they were established with, new connections will use the
+//! newest certificate.
+
+use crate::{
+	error::{Error, Result},
+	sink::{CertificateBundle, PostWriteHook},
+};
+use openssl::{
+	pkey::{PKey, Private},
+	ssl::{
+		ClientHelloResponse, SslAcceptor, SslConnector, SslContextBuilder, SslMethod, SslVerifyMode,
+	},
+	x509::{store::X509StoreBuilder, X509},
+};
+use std::{
+	path::Path,
+	sync::{Arc, PoisonError, RwLock},
+};
+use tracing::{debug, instrument};
+
+/// The certificate, key, and trusted roots that make up the TLS identity of
+/// a service.
+#[derive(Clone)]
+pub struct TlsIdentity {
+	/// The leaf certificate.
+	certificate: X509,
+	/// Any intermediates to serve alongside the leaf certificate.
+	chain: Vec<X509>,
+	/// The private key for the leaf certificate.
+	private_key: PKey<Private>,
+	/// The roots used to validate the other side of the connection.
+	roots: Vec<X509>,
+}
+
+impl std::fmt::Debug for TlsIdentity {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("TlsIdentity")
+			.field("certificate", &self.certificate.subject_name())
+			.field("chain_len", &self.chain.len())
+			.field("roots_len", &self.roots.len())
+			.finish()
+	}
+}
+
+impl TlsIdentity {
+	/// Construct an identity from PEM encoded data.
+	///
+	/// `cert_chain_pem` should contain the leaf certificate, followed by any
+	/// intermediates. `roots_pem` may contain multiple root certificates.
+	///
+	/// # Errors
+	///
+	/// - When any of the PEM data fails to parse.
+	/// - When there are no certificates, or roots present.
+	pub fn from_pem(cert_chain_pem: &[u8], key_pem: &[u8], roots_pem: &[u8]) -> Result<Self> {
+		let mut certs = X509::stack_from_pem(cert_chain_pem)?.into_iter();
+		let certificate = certs.next().ok_or_else(|| {
+			Error::Trust("No certificates present in certificate chain".to_owned())
+		})?;
+		let roots = X509::stack_from_pem(roots_pem)?;
+		if roots.is_empty() {
+			return Err(Error::Trust("No root certificates present".to_owned()));
+		}
+
+		Ok(Self {
+			certificate,
+			chain: certs.collect(),
+			private_key: PKey::private_key_from_pem(key_pem)?,
+			roots,
+		})
+	}
+
+	/// Construct an identity from PEM encoded files, such as the ones written
+	/// by a `sink::FileSink`.
+	///
+	/// # Errors
+	///
+	/// - When any of the files can't be read.
+	/// - When any of the PEM data fails to parse.
+	pub fn from_files(cert_chain_path: &Path, key_path: &Path, roots_path: &Path) -> Result<Self> {
+		Self::from_pem(
+			&std::fs::read(cert_chain_path)?,
+			&std::fs::read(key_path)?,
+			&std::fs::read(roots_path)?,
+		)
+	}
+
+	/// Create a new identity from a freshly issued bundle. If the bundle does
+	/// not contain a private key (e.g. it was a renewal), the key from this
+	/// identity is kept.
+	///
+	/// # Errors
+	///
+	/// - When any of the PEM data fails to parse.
+	pub fn with_bundle(&self, bundle: &CertificateBundle) -> Result<Self> {
+		let private_key = if let Some(key) = &bundle.private_key {
+			PKey::private_key_from_pem(key.as_bytes())?
+		} else {
+			self.private_key.clone()
+		};
+		let mut chain = Vec::with_capacity(bundle.chain.len());
+		for pem in &bundle.chain {
+			chain.extend(X509::stack_from_pem(pem.as_bytes())?);
+		}
+
+		Ok(Self {
+			certificate: X509::from_pem(bundle.certificate.as_bytes())?,
+			chain,
+			private_key,
+			roots: self.roots.clone(),
+		})
+	}
+
+	/// Create a new identity that trusts a different set of roots.
+	///
+	/// # Errors
+	///
+	/// - When the PEM data fails to parse, or contains no certificates.
+	pub fn with_roots_pem(&self, roots_pem: &[u8]) -> Result<Self> {
+		let roots = X509::stack_from_pem(roots_pem)?;
+		if roots.is_empty() {
+			return Err(Error::Trust("No root certificates present".to_owned()));
+		}
+
+		Ok(Self {
+			roots,
+			..self.clone()
+		})
+	}
+
+	/// Load the certificate, key, and roots into an OpenSSL context.
+	fn configure(&self, ctx: &mut SslContextBuilder, verify: SslVerifyMode) -> Result<()> {
+		ctx.set_certificate(&self.certificate)?;
+		for intermediate in &self.chain {
+			ctx.add_extra_chain_cert(intermediate.clone())?;
+		}
+		ctx.set_private_key(&self.private_key)?;
+		ctx.check_private_key()?;
+
+		let mut store = X509StoreBuilder::new()?;
+		for root in &self.roots {
+			store.add_cert(root.clone())?;
+		}
+		ctx.set_cert_store(store.build());
+		ctx.set_verify(verify);
+		Ok(())
+	}
+}
+
+/// The contexts built from the most recent `TlsIdentity`.
+struct TlsContexts {
+	/// An acceptor for the current identity.
+	acceptor: SslAcceptor,
+	/// A connector for the current identity.
+	connector: SslConnector,
+}
+
+/// An OpenSSL Acceptor, and Connector pair whose identity can be swapped out
+/// while in use.
+///
+/// The acceptor returned from `acceptor` never needs to be replaced, each new
+/// connection is switched over to the latest identity as the client hello is
+/// received. `SslConnector` provides no such hook, so `connector` returns the
+/// connector for the latest identity, and should be called for each new
+/// connection.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::{net::TcpListener, path::{Path, PathBuf}};
+/// # use tinystep::{sink::FileSink, tls::{ReloadableTls, TlsIdentity}};
+/// let tls = ReloadableTls::new(
+///   TlsIdentity::from_files(
+///     Path::new("server.crt"),
+///     Path::new("server.key"),
+///     Path::new("root_ca.crt"),
+///   ).unwrap(),
+///   true,
+/// ).unwrap();
+/// // Renewals written by this sink will be picked up by new connections.
+/// let sink = FileSink::new(PathBuf::from("server.crt")).with_hook(tls.reload_hook());
+///
+/// let acceptor = tls.acceptor();
+/// for stream in TcpListener::bind("0.0.0.0:8443").unwrap().incoming() {
+///   let _tls_stream = acceptor.accept(stream.unwrap());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ReloadableTls {
+	/// The identity everything was last built from.
+	identity: Arc<RwLock<TlsIdentity>>,
+	/// The contexts built from `identity`.
+	contexts: Arc<RwLock<TlsContexts>>,
+	/// The acceptor handed out to callers, which swaps to the latest context
+	/// during the handshake.
+	acceptor: SslAcceptor,
+	/// If servers should require clients to present a certificate.
+	require_client_cert: bool,
+}
+
+impl std::fmt::Debug for ReloadableTls {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("ReloadableTls")
+			.field("identity", &self.identity)
+			.field("require_client_cert", &self.require_client_cert)
+			.finish()
+	}
+}
+
+impl ReloadableTls {
+	/// Create a new acceptor/connector pair for a particular identity.
+	///
+	/// If `require_client_cert` is true, the acceptor will reject any client
+	/// that does not present a certificate signed by one of the roots (mTLS).
+	///
+	/// # Errors
+	///
+	/// - When OpenSSL fails to accept the identity, e.g. the key does not match
+	///   the certificate.
+	#[instrument]
+	pub fn new(identity: TlsIdentity, require_client_cert: bool) -> Result<Self> {
+		let contexts = Arc::new(RwLock::new(Self::build_contexts(
+			&identity,
+			require_client_cert,
+		)?));
+
+		let mut front = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+		identity.configure(&mut front, Self::server_verify_mode(require_client_cert))?;
+		let callback_contexts = contexts.clone();
+		front.set_client_hello_callback(move |ssl, _alert| {
+			if let Ok(current) = callback_contexts.read() {
+				ssl.set_ssl_context(current.acceptor.context())?;
+			}
+			Ok(ClientHelloResponse::SUCCESS)
+		});
+
+		Ok(Self {
+			identity: Arc::new(RwLock::new(identity)),
+			contexts,
+			acceptor: front.build(),
+			require_client_cert,
+		})
+	}
+
+	/// An acceptor that always serves the latest identity.
+	#[must_use]
+	pub fn acceptor(&self) -> SslAcceptor {
+		self.acceptor.clone()
+	}
+
+	/// A connector for the latest identity. Call this for each new connection
+	/// rather than holding onto the result.
+	#[must_use]
+	pub fn connector(&self) -> SslConnector {
+		self.contexts
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.connector
+			.clone()
+	}
+
+	/// The identity currently being served.
+	#[must_use]
+	pub fn identity(&self) -> TlsIdentity {
+		self.identity
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+
+	/// Swap over to an entirely new identity.
+	///
+	/// # Errors
+	///
+	/// - When OpenSSL fails to accept the identity, in which case the previous
+	///   identity remains in use.
+	#[instrument]
+	pub fn reload(&self, identity: TlsIdentity) -> Result<()> {
+		let contexts = Self::build_contexts(&identity, self.require_client_cert)?;
+		// Nothing is left half updated if a reload panicked, we only ever
+		// replace the whole value.
+		let mut identity_lock = self
+			.identity
+			.write()
+			.unwrap_or_else(PoisonError::into_inner);
+		*self
+			.contexts
+			.write()
+			.unwrap_or_else(PoisonError::into_inner) = contexts;
+		*identity_lock = identity;
+		debug!("Reloaded TLS Identity");
+		Ok(())
+	}
+
+	/// Swap over to a freshly issued certificate, keeping the current private
+	/// key if the bundle doesn't contain one.
+	///
+	/// # Errors
+	///
+	/// - When the bundle fails to parse, or OpenSSL rejects it.
+	pub fn reload_bundle(&self, bundle: &CertificateBundle) -> Result<()> {
+		self.reload(self.identity().with_bundle(bundle)?)
+	}
+
+	/// Swap over to a new set of trusted roots, keeping the current
+	/// certificate, and key.
+	///
+	/// # Errors
+	///
+	/// - When the roots fail to parse.
+	pub fn reload_roots(&self, roots_pem: &[u8]) -> Result<()> {
+		self.reload(self.identity().with_roots_pem(roots_pem)?)
+	}
+
+	/// A hook for a `sink::FileSink` that reloads this identity whenever a
+	/// new certificate is written.
+	#[must_use]
+	pub fn reload_hook(&self) -> PostWriteHook {
+		let this = self.clone();
+		PostWriteHook::Callback(Arc::new(move |bundle| this.reload_bundle(bundle)))
+	}
+
+	/// How servers should verify clients.
+	fn server_verify_mode(require_client_cert: bool) -> SslVerifyMode {
+		if require_client_cert {
+			SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT
+		} else {
+			SslVerifyMode::NONE
+		}
+	}
+
+	/// Build a fresh set of contexts for an identity.
+	fn build_contexts(identity: &TlsIdentity, require_client_cert: bool) -> Result<TlsContexts> {
+		let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+		identity.configure(&mut acceptor, Self::server_verify_mode(require_client_cert))?;
+		let mut connector = SslConnector::builder(SslMethod::tls_client())?;
+		identity.configure(&mut connector, SslVerifyMode::PEER)?;
+
+		Ok(TlsContexts {
+			acceptor: acceptor.build(),
+			connector: connector.build(),
+		})
+	}
+}
+
+#[cfg(all(test, unix))]
+mod unit_tests {
+	use super::*;
+	use crate::test_util::{cert, new_key, CertOptions};
+	use openssl::nid::Nid;
+	use std::os::unix::net::UnixStream;
+
+	/// A certificate for `localhost` issued by the root.
+	fn leaf(cn: &str, key: &PKey<Private>, root_key: &PKey<Private>) -> X509 {
+		cert(
+			cn,
+			key,
+			CertOptions {
+				issuer: Some(("root", root_key)),
+				ca: false,
+				dns_name: Some("localhost"),
+				..CertOptions::default()
+			},
+		)
+	}
+
+	fn peer_common_name(tls: &ReloadableTls) -> String {
+		let (server_sock, client_sock) = UnixStream::pair().unwrap();
+		let acceptor = tls.acceptor();
+		let server = std::thread::spawn(move || {
+			let _ = acceptor.accept(server_sock);
+		});
+		let stream = tls.connector().connect("localhost", client_sock).unwrap();
+		let peer = stream.ssl().peer_certificate().unwrap();
+		let cn = String::from_utf8_lossy(
+			peer.subject_name()
+				.entries_by_nid(Nid::COMMONNAME)
+				.next()
+				.unwrap()
+				.data()
+				.as_slice(),
+		)
+		.into_owned();
+		drop(stream);
+		server.join().unwrap();
+		cn
+	}
+
+	#[test]
+	pub fn test_reload_identity() {
+		let root_key = new_key();
+		let root = cert("root", &root_key, CertOptions::default());
+		let key = new_key();
+		let first = leaf("first", &key, &root_key);
+		let second = leaf("second", &key, &root_key);
+
+		let tls = ReloadableTls::new(
+			TlsIdentity::from_pem(
+				&first.to_pem().unwrap(),
+				&key.private_key_to_pem_pkcs8().unwrap(),
+				&root.to_pem().unwrap(),
+			)
+			.unwrap(),
+			true,
+		)
+		.unwrap();
+		assert_eq!(peer_common_name(&tls), "first");
+
+		tls.reload_bundle(&CertificateBundle {
+			certificate: String::from_utf8(second.to_pem().unwrap()).unwrap(),
+			chain: Vec::new(),
+			private_key: None,
+		})
+		.unwrap();
+		assert_eq!(peer_common_name(&tls), "second");
+	}
+}
@@ -0,0 +1,257 @@
+//! Fetching, verifying, and caching the root certificate of a smallstep
+//! instance.
+//!
+//! When only the fingerprint of a root certificate is known we fetch the root
+//! from the smallstep instance itself, and verify it against the fingerprint.
+//! Since the http client we use (curl) can only read trusted roots from disk,
+//! the root then needs to be written somewhere. `RootCache` controls where.
+
+use crate::{
+	builder::NetworkSettings,
+	error::{json_or_error, Error, Result},
+	sink::write_file_atomic,
+	types::StepRootResponse,
+};
+use isahc::{config::SslOption, prelude::*};
+use openssl::{hash::MessageDigest, x509::X509};
+use std::{
+	path::{Path, PathBuf},
+	sync::atomic::{AtomicUsize, Ordering},
+};
+use tracing::{debug, warn};
+
+/// Where the root certificate for a smallstep instance should be kept once
+/// it has been fetched, and verified against its fingerprint.
+#[derive(Clone, Debug, Default)]
+pub enum RootCache {
+	/// Keep the root in the default cache directory. This is `$STEPPATH/certs`
+	/// if `$STEPPATH` is set, otherwise `$XDG_CACHE_HOME/tinystep`, or
+	/// `~/.cache/tinystep`. If none of those can be found the root is treated
+	/// as `RootCache::Ephemeral`, rather than caching it somewhere shared like
+	/// the temporary directory where anyone could replace it.
+	#[default]
+	Default,
+	/// Keep the root in a particular directory.
+	Directory(PathBuf),
+	/// Never reuse a root between runs. The root is always fetched, and only
+	/// kept in a private temporary file which is removed once every client
+	/// using it has been dropped.
+	///
+	/// Ideally this would be kept purely in memory, but curl can only read
+	/// trusted roots from a file.
+	Ephemeral,
+}
+
+/// A root certificate which has been fetched, and verified.
+#[derive(Debug)]
+pub(crate) struct CachedRoot {
+	/// Where the root can be read from.
+	pub path: PathBuf,
+	/// Removes the root once dropped, if the root is ephemeral.
+	pub guard: Option<EphemeralRoot>,
+}
+
+impl CachedRoot {
+	/// A root certificate that was provided by the caller.
+	pub fn from_path(path: PathBuf) -> Self {
+		Self { path, guard: None }
+	}
+}
+
+/// A root certificate file that should be removed once it's no longer used.
+#[derive(Debug)]
+pub(crate) struct EphemeralRoot(PathBuf);
+
+impl Drop for EphemeralRoot {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_file(&self.0);
+	}
+}
+
+/// Used to give every ephemeral root its own file.
+static NEXT_EPHEMERAL_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl RootCache {
+	/// The directory the default cache lives in, or `None` if there's no
+	/// private directory to cache in.
+	#[must_use]
+	pub fn default_directory() -> Option<PathBuf> {
+		if let Some(step_path) = std::env::var_os("STEPPATH") {
+			return Some(PathBuf::from(step_path).join("certs"));
+		}
+		if let Some(cache_home) = std::env::var_os("XDG_CACHE_HOME") {
+			return Some(PathBuf::from(cache_home).join("tinystep"));
+		}
+		std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache").join("tinystep"))
+	}
+
+	/// Get a verified root for a particular fingerprint, reusing a previously
+	/// cached root if it still matches the fingerprint.
+	pub(crate) fn root_for(
+		&self,
+		base_url: &str,
+		fingerprint: &str,
+		network: &NetworkSettings,
+	) -> Result<CachedRoot> {
+		let file_name = format!("smallstep-ca-{}.pem", fingerprint);
+		let directory = match self {
+			RootCache::Default => match Self::default_directory() {
+				Some(dir) => dir,
+				None => {
+					debug!("No private directory to cache the root certificate in, not caching it");
+					return Self::ephemeral_root(base_url, fingerprint, network, &file_name);
+				}
+			},
+			RootCache::Directory(dir) => dir.clone(),
+			RootCache::Ephemeral => {
+				return Self::ephemeral_root(base_url, fingerprint, network, &file_name);
+			}
+		};
+
+		let path = directory.join(file_name);
+		match read_verified_root(&path, fingerprint) {
+			Ok(true) => {
+				debug!("Reusing cached root certificate: {:?}", path);
+				return Ok(CachedRoot { path, guard: None });
+			}
+			Ok(false) => warn!(
+				"Cached root certificate: {:?} does not match fingerprint: {}, re-fetching",
+				path, fingerprint
+			),
+			Err(_) => {}
+		}
+
+		let root = fetch_root_certificate_with(base_url, fingerprint, network)?;
+		std::fs::create_dir_all(&directory)?;
+		write_file_atomic(&path, root.as_bytes(), 0o644, None, None)?;
+		Ok(CachedRoot { path, guard: None })
+	}
+
+	/// Fetch a root that's only kept for as long as it's being used.
+	fn ephemeral_root(
+		base_url: &str,
+		fingerprint: &str,
+		network: &NetworkSettings,
+		file_name: &str,
+	) -> Result<CachedRoot> {
+		let path = std::env::temp_dir().join(format!(
+			".tinystep-{}-{}-{}",
+			std::process::id(),
+			NEXT_EPHEMERAL_ID.fetch_add(1, Ordering::Relaxed),
+			file_name
+		));
+		let root = fetch_root_certificate_with(base_url, fingerprint, network)?;
+		write_file_atomic(&path, root.as_bytes(), 0o600, None, None)?;
+		Ok(CachedRoot {
+			path: path.clone(),
+			guard: Some(EphemeralRoot(path)),
+		})
+	}
+}
+
+/// Calculate the fingerprint smallstep uses to identify a certificate, the
+/// lowercase hex encoded SHA-256 digest of the certificate.
+///
+/// # Errors
+///
+/// - When the PEM data is not a certificate.
+pub fn fingerprint_pem(pem: &[u8]) -> Result<String> {
+	let raw_digest = X509::from_pem(pem)?.digest(MessageDigest::sha256())?;
+	Ok(hex::encode(raw_digest).to_lowercase())
+}
+
+/// Check if a previously written root still matches its fingerprint.
+fn read_verified_root(path: &Path, fingerprint: &str) -> Result<bool> {
+	let contents = std::fs::read(path)?;
+	Ok(fingerprint_pem(&contents)? == fingerprint.to_lowercase())
+}
+
+/// Fetch the root certificate for a particular smallstep instance based off
+/// of its fingerprint, returning the PEM Encoded root once it has been
+/// verified to match the fingerprint.
+///
+/// # Errors
+///
+/// - When the root can't be fetched.
+/// - When the root does not match the fingerprint.
+pub fn fetch_root_certificate(base_url: &str, fingerprint: &str) -> Result<String> {
+	fetch_root_certificate_with(base_url, fingerprint, &NetworkSettings::default())
+}
+
+/// Fetch, and verify a root certificate going through a particular proxy,
+/// resolver, etc.
+pub(crate) fn fetch_root_certificate_with(
+	base_url: &str,
+	fingerprint: &str,
+	network: &NetworkSettings,
+) -> Result<String> {
+	// This URL is signed by the root certificate we're fetching, so until
+	// it's verified it could be anyone, and gets none of our extra headers.
+	let client = network
+		.unauthenticated_client_builder()
+		.ssl_options(SslOption::DANGER_ACCEPT_INVALID_CERTS)
+		.build()?;
+	let resp = json_or_error::<StepRootResponse>(
+		client.get(format!("{}/root/{}", base_url, fingerprint))?,
+	)?;
+	let digest = fingerprint_pem(resp.ca.as_bytes())?;
+
+	debug!(
+		"Received Digest: [{}] from base url: [{}], comparing to argument: [{}]",
+		digest, base_url, fingerprint
+	);
+	if digest != fingerprint.to_lowercase() {
+		return Err(Error::FingerprintMismatch {
+			expected: fingerprint.to_lowercase(),
+			actual: digest,
+		});
+	}
+
+	Ok(resp.ca)
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use crate::test_util::{root_pem, serve};
+
+	#[test]
+	pub fn test_reuses_cached_root() {
+		let pem = root_pem("root");
+		let fingerprint = fingerprint_pem(pem.as_bytes()).unwrap();
+
+		let dir = std::env::temp_dir().join(format!("tinystep-trust-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let cached_path = dir.join(format!("smallstep-ca-{}.pem", fingerprint));
+		std::fs::write(&cached_path, &pem).unwrap();
+
+		// The url is never contacted since the cached root matches.
+		let root = RootCache::Directory(dir.clone())
+			.root_for(
+				"https://tinystep.invalid",
+				&fingerprint,
+				&NetworkSettings::default(),
+			)
+			.unwrap();
+		assert_eq!(root.path, cached_path);
+		assert!(root.guard.is_none());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	pub fn test_fingerprint_fetch_sends_no_headers() {
+		let (base_url, requests) = serve(|_| (404, String::new()));
+		let network = NetworkSettings {
+			no_proxy: vec!["127.0.0.1".to_owned()],
+			default_headers: vec![("X-Tinystep-Secret".to_owned(), "hunter2".to_owned())],
+			..NetworkSettings::default()
+		};
+		assert!(fetch_root_certificate_with(&base_url, "abcd", &network).is_err());
+
+		let request = requests.recv().unwrap().to_lowercase();
+		assert!(request.starts_with("get /root/abcd "));
+		assert!(request.contains("user-agent:tinystep/"));
+		assert!(!request.contains("x-tinystep-secret"));
+	}
+}
@@ -2,9 +2,10 @@
 //! server.
 
 use crate::types::{
-	StepAWSProvisioner, StepAcmeProvisioner, StepAzureProvisioner, StepGCPProvisioner,
-	StepJWKProvisioner, StepK8SSAProvisioner, StepOIDCProvisioner, StepProvisioner,
-	StepProvisionerType, StepSSHPOPProvisioner, StepX5CProvisioner,
+	GoDuration, StepAWSProvisioner, StepAcmeProvisioner, StepAzureProvisioner, StepGCPProvisioner,
+	StepJWKProvisioner, StepK8SSAProvisioner, StepNebulaProvisioner, StepOIDCProvisioner,
+	StepProvisioner, StepProvisionerType, StepSCEPProvisioner, StepSSHPOPProvisioner,
+	StepX5CProvisioner,
 };
 use chrono::Duration;
 use serde::{
@@ -18,7 +19,7 @@ use std::str::FromStr;
 /// you need to tell serde what type you ran into that was unexpected, this
 /// simplifies that for json deserialization by figuring it out for you.
 #[must_use]
-pub fn find_unknown_type(to_find_type: &JsonValue) -> DeUnexpected {
+pub fn find_unknown_type(to_find_type: &JsonValue) -> DeUnexpected<'_> {
 	if to_find_type.is_array() {
 		DeUnexpected::Other("array")
 	} else if to_find_type.is_boolean() {
@@ -45,195 +46,25 @@ pub fn find_unknown_type(to_find_type: &JsonValue) -> DeUnexpected {
 }
 
 /// Parsing a `time.Duration` from golang. Can be used with the
-/// `deserialize_with` attribute for serde.
-///
-/// This actually isn't perfect and does rounding of second or less float
-/// values. This is okay for smallstep, but note: may not be perfect
-/// for you.
+/// `deserialize_with` attribute for serde. See: `GoDuration` for parsing
+/// durations outside of serde.
 ///
 /// # Errors
 ///
 /// * `DeError::invalid_type` - when the type is not a string containing a duration.
-/// * `DeError::custom` - Invalid timestamp.
-/// * `DeError::custom` - time overflow.
-#[allow(clippy::too_many_lines, clippy::cast_possible_truncation)]
+/// * `DeError::custom` - Invalid duration, or overflow.
 pub fn from_golang_duration<'a, D>(deserializer: D) -> std::result::Result<Duration, D::Error>
 where
 	D: Deserializer<'a>,
 {
-	let parsed_str = JsonValue::deserialize(deserializer)?;
-	if !parsed_str.is_string() {
-		return Err(DeError::invalid_type(
-			find_unknown_type(&parsed_str),
+	match JsonValue::deserialize(deserializer)? {
+		JsonValue::String(dur) => GoDuration::parse(&dur)
+			.map(GoDuration::to_duration)
+			.map_err(DeError::custom),
+		other => Err(DeError::invalid_type(
+			find_unknown_type(&other),
 			&"A string duration",
-		));
-	}
-	let full_str = parsed_str.to_string();
-	let subtract_quotes = full_str.trim_start_matches('"').trim_end_matches('"');
-	let should_negate = subtract_quotes.starts_with('-');
-	let dur_as_str = subtract_quotes
-		.trim_start_matches('-')
-		.trim_start_matches('+');
-
-	let mut dur = Duration::zero();
-	let mut tmp_number_str = String::new();
-	let mut skip_next_char = false;
-
-	for (idx, car) in dur_as_str.chars().enumerate() {
-		if skip_next_char {
-			skip_next_char = false;
-			continue;
-		}
-		let as_u8 = car as u8;
-		if car == '.' || (as_u8 >= (b'0') && as_u8 <= (b'9')) {
-			tmp_number_str.push(car);
-		} else {
-			match car {
-				'h' => {
-					let pfr = tmp_number_str.parse();
-					if pfr.is_err() {
-						return Err(DeError::custom(format!(
-							"Invalid time duration: `{}`",
-							tmp_number_str
-						)));
-					}
-					tmp_number_str = String::new();
-					let parsed_float: f64 = pfr.unwrap();
-
-					let potential_new_dur = dur.checked_add(&Duration::seconds(
-						((parsed_float * (60_f64)) * 60_f64).round() as i64,
-					));
-					if potential_new_dur.is_none() {
-						return Err(DeError::custom("overflow time!"));
-					}
-					dur = potential_new_dur.unwrap();
-				}
-				'm' => {
-					if idx + 1 < dur_as_str.len()
-						&& dur_as_str.get(idx + 1..idx + 2).unwrap() == "s"
-					{
-						skip_next_char = true;
-						let pfr = tmp_number_str.parse();
-						if pfr.is_err() {
-							return Err(DeError::custom(format!(
-								"Invalid time duration: `{}`",
-								tmp_number_str
-							)));
-						}
-						tmp_number_str = String::new();
-						let parsed_float: f64 = pfr.unwrap();
-
-						let potential_new_dur =
-							dur.checked_add(&Duration::milliseconds(parsed_float.round() as i64));
-						if potential_new_dur.is_none() {
-							return Err(DeError::custom("overflow time!"));
-						}
-						dur = potential_new_dur.unwrap();
-					} else {
-						let pfr = tmp_number_str.parse();
-						if pfr.is_err() {
-							return Err(DeError::custom(format!(
-								"Invalid time duration: `{}`",
-								tmp_number_str
-							)));
-						}
-						tmp_number_str = String::new();
-						let parsed_float: f64 = pfr.unwrap();
-
-						let potential_new_dur = dur.checked_add(&Duration::seconds(
-							(parsed_float * (60_f64)).round() as i64,
-						));
-						if potential_new_dur.is_none() {
-							return Err(DeError::custom("overflow time!"));
-						}
-						dur = potential_new_dur.unwrap();
-					}
-				}
-				'u' | 'µ' | 'μ' => {
-					if idx + 1 < dur_as_str.len()
-						&& dur_as_str.get(idx + 1..idx + 2).unwrap() == "s"
-					{
-						skip_next_char = true;
-						let pfr = tmp_number_str.parse();
-						if pfr.is_err() {
-							return Err(DeError::custom(format!(
-								"Invalid time duration: `{}`",
-								tmp_number_str
-							)));
-						}
-						tmp_number_str = String::new();
-						let parsed_float: f64 = pfr.unwrap();
-
-						let potential_new_dur =
-							dur.checked_add(&Duration::microseconds(parsed_float.round() as i64));
-						if potential_new_dur.is_none() {
-							return Err(DeError::custom("overflow time!"));
-						}
-						dur = potential_new_dur.unwrap();
-					} else {
-						return Err(DeError::custom(
-							"Unknown time duration, isn't `h`, `m`, `s`, `us`, `ms`, `ns`",
-						));
-					}
-				}
-				'n' => {
-					if idx + 1 < dur_as_str.len()
-						&& dur_as_str.get(idx + 1..idx + 2).unwrap() == "s"
-					{
-						skip_next_char = true;
-						let pfr = tmp_number_str.parse();
-						if pfr.is_err() {
-							return Err(DeError::custom(format!(
-								"Invalid time duration: `{}`",
-								tmp_number_str
-							)));
-						}
-						tmp_number_str = String::new();
-						let parsed_float: f64 = pfr.unwrap();
-
-						let potential_new_dur =
-							dur.checked_add(&Duration::nanoseconds(parsed_float.round() as i64));
-						if potential_new_dur.is_none() {
-							return Err(DeError::custom("overflow time!"));
-						}
-						dur = potential_new_dur.unwrap();
-					} else {
-						return Err(DeError::custom(
-							"Unknown time duration, isn't `h`, `m`, `s`, `us`, `ms`, `ns`",
-						));
-					}
-				}
-				's' => {
-					let pfr = tmp_number_str.parse();
-					if pfr.is_err() {
-						return Err(DeError::custom(format!(
-							"Invalid time duration: `{}`",
-							tmp_number_str
-						)));
-					}
-					tmp_number_str = String::new();
-					let parsed_float: f64 = pfr.unwrap();
-
-					let potential_new_dur =
-						dur.checked_add(&Duration::seconds(parsed_float.round() as i64));
-					if potential_new_dur.is_none() {
-						return Err(DeError::custom("overflow time!"));
-					}
-					dur = potential_new_dur.unwrap();
-				}
-				_ => {
-					return Err(DeError::custom(
-						"Unknown time duration, isn't `h`, `m`, `s`, `us`, `ms`, `ns`",
-					));
-				}
-			}
-		}
-	}
-
-	if should_negate {
-		Ok(-dur)
-	} else {
-		Ok(dur)
+		)),
 	}
 }
 
@@ -241,6 +72,10 @@ where
 /// `Ok(None)`. This should never actually error itself. Can be used with the
 /// `deserialize_with` attribute for serde.
 ///
+/// Note that this makes a malformed duration indistinguishable from a missing
+/// one. If you need to tell them apart see: `from_golang_duration_opt_strict`,
+/// or `ParsedDuration` which keeps the malformed value around.
+///
 /// # Errors
 ///
 /// This method will never error, as it maps errors to: `Ok(None)`.
@@ -258,26 +93,88 @@ where
 	}
 }
 
+/// Parse out an optional golang duration, where `null` is `None`, but unlike
+/// `from_golang_duration_opt` a malformed duration is an error. Can be used
+/// with the `deserialize_with` attribute for serde (along with `default` for
+/// the field to be optional).
+///
+/// # Errors
+///
+/// * `DeError::invalid_type` - when not a string, or null.
+/// * `DeError::invalid_value` - when not a valid duration.
+pub fn from_golang_duration_opt_strict<'a, D>(
+	deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error>
+where
+	D: Deserializer<'a>,
+{
+	match Option::<JsonValue>::deserialize(deserializer)? {
+		None | Some(JsonValue::Null) => Ok(None),
+		Some(value) => from_golang_duration(value)
+			.map(Some)
+			.map_err(DeError::custom),
+	}
+}
+
+/// Every provisioner type tinystep knows how to parse.
+pub(crate) const KNOWN_PROVISIONER_TYPES: &[&str] = &[
+	"JWK", "OIDC", "GCP", "AWS", "Azure", "ACME", "X5C", "K8sSA", "SSHPOP", "SCEP", "Nebula",
+];
+
 /// Deserialize a list of provisioners. This is called
 /// `dynamic_provisioner_list` because smallstep identifies provisioners
 /// by a "type" field, which is dynamic itself. Can be used with the
 /// `deserialize_with` attribute for serde.
 ///
+/// Provisioners of a type tinystep doesn't know about are returned as a
+/// `StepProvisioner::Unknown`, if you'd rather fail see:
+/// `dynamic_provisioner_list_strict`.
+///
 /// # Errors
 ///
 /// * `DeError::invalid_type` - when not an array of objects.
 /// * `DeError::invalid_type` - when the items in the array are not objects.
 /// * `DeError::invalid_type` - when there is no type field that is a string.
-/// * `DeError::unknown_variant` - unknown provisioner type.
 /// * `DeError::custom` - invalid parsed object.
-#[allow(clippy::too_many_lines)]
 pub fn dynamic_provisioner_list<'a, D>(
 	deserializer: D,
 ) -> std::result::Result<Vec<StepProvisioner>, D::Error>
 where
 	D: Deserializer<'a>,
 {
-	let as_any = JsonValue::deserialize(deserializer)?;
+	provisioner_list(JsonValue::deserialize(deserializer)?, false)
+}
+
+/// Deserialize a list of provisioners, the same as
+/// `dynamic_provisioner_list`, except failing on a provisioner of a type
+/// tinystep doesn't know about.
+///
+/// # Errors
+///
+/// * `DeError::invalid_type` - when not an array of objects.
+/// * `DeError::invalid_type` - when the items in the array are not objects.
+/// * `DeError::invalid_type` - when there is no type field that is a string.
+/// * `DeError::unknown_variant` - unknown provisioner type.
+/// * `DeError::custom` - invalid parsed object.
+pub fn dynamic_provisioner_list_strict<'a, D>(
+	deserializer: D,
+) -> std::result::Result<Vec<StepProvisioner>, D::Error>
+where
+	D: Deserializer<'a>,
+{
+	provisioner_list(JsonValue::deserialize(deserializer)?, true)
+}
+
+/// Parse a list of provisioners, when `strict` an unknown provisioner type is
+/// an error, rather than a `StepProvisioner::Unknown`.
+#[allow(clippy::too_many_lines)]
+fn provisioner_list<E>(
+	as_any: JsonValue,
+	strict: bool,
+) -> std::result::Result<Vec<StepProvisioner>, E>
+where
+	E: DeError,
+{
 	if !as_any.is_array() {
 		return Err(DeError::invalid_type(
 			find_unknown_type(&as_any),
@@ -289,7 +186,7 @@ where
 	for any in as_any.as_array().unwrap() {
 		if !any.is_object() {
 			return Err(DeError::invalid_type(
-				find_unknown_type(&any),
+				find_unknown_type(any),
 				&"a provisioner object",
 			));
 		}
@@ -302,17 +199,10 @@ where
 		}
 
 		let type_str = any["type"].as_str().unwrap();
-		let attempt_enum_match = StepProvisionerType::from_str(type_str);
-		if attempt_enum_match.is_err() {
-			return Err(DeError::unknown_variant(
-				type_str,
-				&[
-					"JWK", "OIDC", "GCP", "AWS", "Azure", "ACME", "X5C", "K8sSA", "SSHPOP",
-				],
-			));
-		}
+		let provisioner_type =
+			StepProvisionerType::from_str(type_str).unwrap_or(StepProvisionerType::Unknown);
 
-		match attempt_enum_match.unwrap() {
+		match provisioner_type {
 			StepProvisionerType::JsonWebKey => {
 				let res = serde_json::from_value::<StepJWKProvisioner>(any.clone());
 				if let Err(err_case) = res {
@@ -380,6 +270,30 @@ where
 				}
 				result.push(StepProvisioner::SshKeypairProvisioner(res.unwrap()));
 			}
+			StepProvisionerType::Scep => {
+				let res = serde_json::from_value::<StepSCEPProvisioner>(any.clone());
+				if let Err(err_case) = res {
+					return Err(DeError::custom(err_case.to_string()));
+				}
+				result.push(StepProvisioner::ScepProvisioner(res.unwrap()));
+			}
+			StepProvisionerType::Nebula => {
+				let res = serde_json::from_value::<StepNebulaProvisioner>(any.clone());
+				if let Err(err_case) = res {
+					return Err(DeError::custom(err_case.to_string()));
+				}
+				result.push(StepProvisioner::NebulaProvisioner(res.unwrap()));
+			}
+			StepProvisionerType::Unknown => {
+				if strict {
+					return Err(DeError::unknown_variant(type_str, KNOWN_PROVISIONER_TYPES));
+				}
+				result.push(StepProvisioner::Unknown {
+					typ: type_str.to_owned(),
+					name: any["name"].as_str().unwrap_or_default().to_owned(),
+					raw: any.clone(),
+				});
+			}
 		}
 	}
 
@@ -398,6 +312,14 @@ mod unit_test {
 		pub field_b: Duration,
 	}
 
+	#[derive(Clone, Debug, Deserialize)]
+	pub struct Provisioners {
+		#[serde(deserialize_with = "dynamic_provisioner_list")]
+		pub lenient: Vec<StepProvisioner>,
+		#[serde(deserialize_with = "dynamic_provisioner_list_strict", default)]
+		pub strict: Vec<StepProvisioner>,
+	}
+
 	#[test]
 	pub fn test_deserialize() {
 		let string_a = r#"
@@ -425,4 +347,95 @@ mod unit_test {
 		assert_eq!(the_b.field_a.unwrap().num_milliseconds(), 300);
 		assert_eq!(the_b.field_b.num_seconds(), 9900);
 	}
+
+	#[test]
+	pub fn test_unknown_provisioners() {
+		let parsed = serde_json::from_str::<Provisioners>(
+			r#"{"lenient": [
+				{"type": "ACME", "name": "acme"},
+				{"type": "FUTURE", "name": "future", "someField": 1}
+			]}"#,
+		)
+		.unwrap();
+		assert_eq!(parsed.lenient.len(), 2);
+		assert!(parsed.strict.is_empty());
+		assert_eq!(parsed.lenient[0].typ(), StepProvisionerType::Acme);
+		match &parsed.lenient[1] {
+			StepProvisioner::Unknown { typ, name, raw } => {
+				assert_eq!(typ, "FUTURE");
+				assert_eq!(name, "future");
+				assert_eq!(raw["someField"], 1);
+			}
+			other => panic!("Expected an unknown provisioner, got: {:?}", other),
+		}
+
+		assert!(serde_json::from_str::<Provisioners>(
+			r#"{"lenient": [], "strict": [{"type": "FUTURE", "name": "future"}]}"#,
+		)
+		.is_err());
+	}
+
+	#[test]
+	pub fn test_deserialize_scep_and_nebula() {
+		let string_a = r#"
+		{
+			"lenient": [
+				{
+					"type": "SCEP",
+					"name": "scep",
+					"forceCN": true,
+					"challenge": "secret",
+					"capabilities": ["AES", "POSTPKIOperation", "SHA-256"],
+					"includeRoot": true,
+					"minimumPublicKeyLength": 2048,
+					"encryptionAlgorithmIdentifier": 2,
+					"claims": {"defaultTLSCertDuration": "24h"}
+				},
+				{
+					"type": "Nebula",
+					"name": "nebula",
+					"roots": "LS0tLS1CRUdJTiBORUJVTEEgQ0VSVElGSUNBVEUtLS0tLQo=",
+					"options": {"x509": {"templateFile": "nebula.tpl"}}
+				}
+			]
+		}
+		"#;
+
+		let parsed_a = serde_json::from_str::<Provisioners>(string_a);
+		println!("{:?}", parsed_a);
+		let the_a = parsed_a.unwrap();
+
+		let scep = the_a.lenient[0].as_scep().unwrap();
+		assert_eq!(scep.typ, StepProvisionerType::Scep);
+		assert!(scep.force_cn);
+		assert_eq!(scep.challenge_password.as_deref(), Some("secret"));
+		assert_eq!(scep.capabilities.as_ref().unwrap().len(), 3);
+		assert!(scep.include_root);
+		assert_eq!(scep.minimum_public_key_length, Some(2048));
+		assert_eq!(scep.encryption_algorithm_identifier, Some(2));
+		assert_eq!(
+			the_a.lenient[0]
+				.claims()
+				.unwrap()
+				.default_tls_dur
+				.valid()
+				.unwrap()
+				.num_hours(),
+			24
+		);
+
+		let nebula = the_a.lenient[1].as_nebula().unwrap();
+		assert_eq!(nebula.typ, StepProvisionerType::Nebula);
+		assert_eq!(
+			nebula.roots,
+			"LS0tLS1CRUdJTiBORUJVTEEgQ0VSVElGSUNBVEUtLS0tLQo="
+		);
+		assert_eq!(
+			the_a.lenient[1]
+				.options()
+				.and_then(|opts| opts.x509.as_ref())
+				.and_then(|x509| x509.template_file.as_deref()),
+			Some("nebula.tpl")
+		);
+	}
 }
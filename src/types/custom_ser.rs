@@ -0,0 +1,162 @@
+//! A series of custom serializers, the counterparts to `custom_de`, so the
+//! types provided by a smallstep server can be written back out exactly as
+//! smallstep expects them.
+
+use crate::types::GoDuration;
+use chrono::Duration;
+use serde::{ser::Error as SerError, Serializer};
+
+/// Write a duration as a `time.Duration` golang can parse, in the same format
+/// golang's `time.Duration.String` writes them (see: `GoDuration`). Can be
+/// used with the `serialize_with` attribute for serde, and is the counterpart
+/// to `from_golang_duration`.
+///
+/// # Errors
+///
+/// - When the duration is too large for golang (roughly 292 years).
+/// - When the underlying serializer fails to write a string.
+pub fn to_golang_duration<S>(dur: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	let dur = GoDuration::from_duration(dur)
+		.ok_or_else(|| S::Error::custom(format!("Duration too large for golang: {}", dur)))?;
+	serializer.collect_str(&dur)
+}
+
+/// Write an optional duration as a `time.Duration` golang can parse, or
+/// `null`. Can be used with the `serialize_with` attribute for serde, and is
+/// the counterpart to `from_golang_duration_opt`.
+///
+/// # Errors
+///
+/// When the underlying serializer fails to write a string, or `null`.
+#[allow(clippy::ref_option)]
+pub fn to_golang_duration_opt<S>(dur: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	match dur {
+		Some(dur) => to_golang_duration(dur, serializer),
+		None => serializer.serialize_none(),
+	}
+}
+
+#[cfg(test)]
+mod unit_test {
+	use super::*;
+	use crate::types::{StepProvisioner, StepProvisionersResponseRaw};
+	use serde::Serialize;
+
+	#[derive(Serialize)]
+	pub struct Written {
+		#[serde(serialize_with = "to_golang_duration")]
+		pub field: Duration,
+	}
+
+	#[test]
+	pub fn test_serialize_duration() {
+		for (dur, expected) in &[
+			(Duration::zero(), "0s"),
+			(Duration::hours(24), "24h0m0s"),
+			(Duration::seconds(5400), "1h30m0s"),
+			(Duration::milliseconds(-1500), "-1.5s"),
+			(Duration::nanoseconds(1_001_001), "1.001001ms"),
+		] {
+			assert_eq!(
+				serde_json::to_value(&Written { field: *dur }).unwrap()["field"],
+				*expected
+			);
+		}
+		assert!(serde_json::to_value(&Written {
+			field: Duration::days(365 * 300)
+		})
+		.is_err());
+	}
+
+	#[test]
+	pub fn test_round_trip_provisioners() {
+		let string_a = r#"
+		{
+			"provisioners": [
+				{
+					"type": "JWK",
+					"name": "admin",
+					"key": {"use": "sig", "kty": "EC", "kid": "abc", "crv": "P-256"},
+					"claims": {
+						"maxTLSCertDuration": "2160h",
+						"minTLSCertDuration": "5m30s",
+						"defaultTLSCertDuration": "24hh",
+						"maxUserSSHCertDuration": 5
+					},
+					"options": {"x509": {"templateFile": "templates/leaf.tpl"}},
+					"newField": {"enabled": true}
+				},
+				{
+					"type": "AWS",
+					"name": "aws",
+					"accounts": ["123456789"],
+					"disableCustomSANs": false,
+					"disableTrustOnFirstUse": true,
+					"instanceAge": "1h",
+					"iidRoots": "/etc/aws-roots.pem",
+					"options": {"ssh": {"template": "{}"}}
+				},
+				{
+					"type": "FUTURE",
+					"name": "future",
+					"someField": [1, 2]
+				}
+			],
+			"nextCursor": ""
+		}
+		"#;
+
+		let parsed_a = serde_json::from_str::<StepProvisionersResponseRaw>(string_a).unwrap();
+		let written = serde_json::to_value(&parsed_a).unwrap();
+		println!("{}", written);
+		assert_eq!(written["provisioners"][0]["type"], "JWK");
+		assert_eq!(
+			written["provisioners"][0]["claims"]["maxTLSCertDuration"],
+			"2160h0m0s"
+		);
+		assert_eq!(
+			written["provisioners"][0]["claims"]["minTLSCertDuration"],
+			"5m30s"
+		);
+		// Malformed durations are written back exactly as they were read.
+		assert_eq!(
+			written["provisioners"][0]["claims"]["defaultTLSCertDuration"],
+			"24hh"
+		);
+		assert_eq!(
+			written["provisioners"][0]["claims"]["maxUserSSHCertDuration"],
+			5
+		);
+		assert!(written["provisioners"][0]["claims"]
+			.get("minUserSSHCertDuration")
+			.is_none());
+		assert_eq!(
+			written["provisioners"][0]["options"]["x509"]["templateFile"],
+			"templates/leaf.tpl"
+		);
+		assert_eq!(written["provisioners"][0]["newField"]["enabled"], true);
+		assert_eq!(written["provisioners"][1]["instanceAge"], "1h0m0s");
+		assert_eq!(written["provisioners"][1]["iidRoots"], "/etc/aws-roots.pem");
+		assert_eq!(
+			written["provisioners"][1]["options"]["ssh"]["template"],
+			"{}"
+		);
+		assert_eq!(
+			written["provisioners"][2],
+			serde_json::from_str::<serde_json::Value>(string_a).unwrap()["provisioners"][2]
+		);
+
+		let parsed_b =
+			serde_json::from_value::<StepProvisionersResponseRaw>(written.clone()).unwrap();
+		assert_eq!(serde_json::to_value(&parsed_b).unwrap(), written);
+		let single =
+			serde_json::from_value::<StepProvisioner>(written["provisioners"][0].clone()).unwrap();
+		assert_eq!(single.name(), "admin");
+	}
+}
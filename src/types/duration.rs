@@ -0,0 +1,562 @@
+//! Types for the golang `time.Duration`s smallstep uses in its configuration.
+
+use crate::types::to_golang_duration;
+use chrono::Duration;
+use serde::{de::Error as DeError, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value as JsonValue;
+use std::{
+	convert::TryFrom,
+	fmt::{Display, Formatter, Result as FmtResult},
+	str::FromStr,
+};
+
+/// The largest number of nanoseconds in a golang `time.Duration`, when
+/// negative.
+const MAX_NANOS: u128 = 1 << 63;
+
+/// A golang `time.Duration`, a signed number of nanoseconds. This parses
+/// exactly what golang's `time.ParseDuration` does, and displays exactly what
+/// golang's `time.Duration.String` does, without ever rounding, so it can be
+/// used anywhere a user gives a duration the way smallstep expects them.
+///
+/// # Examples
+///
+/// ```
+/// # use tinystep::types::GoDuration;
+/// let not_after = "8h".parse::<GoDuration>().unwrap();
+/// assert_eq!(not_after.to_duration(), chrono::Duration::hours(8));
+/// assert_eq!(not_after.to_string(), "8h0m0s");
+/// assert_eq!("-1.5µs".parse::<GoDuration>().unwrap().as_nanos(), -1500);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GoDuration(i64);
+
+impl GoDuration {
+	/// Construct a duration from a number of nanoseconds.
+	#[must_use]
+	pub fn from_nanos(nanos: i64) -> Self {
+		GoDuration(nanos)
+	}
+
+	/// Construct a duration from a `chrono::Duration`, or `None` if it's
+	/// too large for golang (roughly 292 years).
+	#[must_use]
+	pub fn from_duration(dur: &Duration) -> Option<Self> {
+		dur.num_nanoseconds().map(GoDuration)
+	}
+
+	/// The number of nanoseconds in this duration.
+	#[must_use]
+	pub fn as_nanos(self) -> i64 {
+		self.0
+	}
+
+	/// This duration as a `chrono::Duration`.
+	#[must_use]
+	pub fn to_duration(self) -> Duration {
+		Duration::nanoseconds(self.0)
+	}
+
+	/// Parse a duration the same way golang's `time.ParseDuration` does,
+	/// returning an error message on failure. Fractions of a unit smaller than
+	/// a nanosecond are truncated.
+	pub(crate) fn parse(to_parse: &str) -> std::result::Result<Self, String> {
+		let invalid = || format!("Invalid time duration: `{}`", to_parse);
+		let (negative, mut rest) = match to_parse.as_bytes().first() {
+			Some(b'-') => (true, &to_parse[1..]),
+			Some(b'+') => (false, &to_parse[1..]),
+			_ => (false, to_parse),
+		};
+		// Golang allows a bare zero, but every other number needs a unit.
+		if rest == "0" {
+			return Ok(GoDuration(0));
+		}
+		if rest.is_empty() {
+			return Err(invalid());
+		}
+
+		let mut nanos: u128 = 0;
+		while !rest.is_empty() {
+			let (whole, after) = split_digits(rest);
+			let (fraction, after) = match after.strip_prefix('.') {
+				Some(after) => split_digits(after),
+				None => ("", after),
+			};
+			if whole.is_empty() && fraction.is_empty() {
+				return Err(invalid());
+			}
+			let unit_len = after
+				.find(|car: char| car == '.' || car.is_ascii_digit())
+				.unwrap_or(after.len());
+			let (unit, after) = after.split_at(unit_len);
+			let unit_nanos: u128 = match unit {
+				"ns" => 1,
+				"us" | "µs" | "μs" => 1_000,
+				"ms" => 1_000_000,
+				"s" => 1_000_000_000,
+				"m" => 60 * 1_000_000_000,
+				"h" => 60 * 60 * 1_000_000_000,
+				"" => {
+					return Err(format!("Missing unit in time duration: `{}`", to_parse));
+				}
+				_ => {
+					return Err(format!(
+						"Unknown unit `{}` in time duration: `{}`",
+						unit, to_parse
+					));
+				}
+			};
+
+			let mut whole_value: u128 = 0;
+			for digit in whole.bytes() {
+				whole_value = whole_value * 10 + u128::from(digit - b'0');
+				if whole_value > MAX_NANOS {
+					return Err(invalid());
+				}
+			}
+			// Only the digits that could possibly matter, even an hour only has
+			// thirteen digits worth of nanoseconds.
+			let (mut fraction_value, mut scale): (u128, u128) = (0, 1);
+			for digit in fraction.bytes().take(18) {
+				fraction_value = fraction_value * 10 + u128::from(digit - b'0');
+				scale *= 10;
+			}
+
+			nanos += whole_value * unit_nanos + fraction_value * unit_nanos / scale;
+			if nanos > MAX_NANOS {
+				return Err(invalid());
+			}
+			rest = after;
+		}
+
+		// Only a negative duration can reach `MAX_NANOS` exactly.
+		let nanos = i128::try_from(nanos).map_err(|_| invalid())?;
+		i64::try_from(if negative { -nanos } else { nanos })
+			.map(GoDuration)
+			.map_err(|_| invalid())
+	}
+}
+
+/// Split a string into its leading ascii digits, and everything after them.
+fn split_digits(to_split: &str) -> (&str, &str) {
+	to_split.split_at(
+		to_split
+			.find(|car: char| !car.is_ascii_digit())
+			.unwrap_or(to_split.len()),
+	)
+}
+
+/// Write the fractional part of a number that has `precision` digits after
+/// the decimal point, without any trailing zeros.
+fn write_fraction(f: &mut Formatter<'_>, fraction: u64, precision: usize) -> FmtResult {
+	if fraction == 0 {
+		return Ok(());
+	}
+	let digits = format!("{:0width$}", fraction, width = precision);
+	write!(f, ".{}", digits.trim_end_matches('0'))
+}
+
+impl Display for GoDuration {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		let nanos = self.0.unsigned_abs();
+		if nanos == 0 {
+			return write!(f, "0s");
+		}
+		if self.0 < 0 {
+			write!(f, "-")?;
+		}
+
+		// Less than a second is written in the largest unit that fits, while
+		// anything larger is written in hours, minutes, and seconds.
+		if nanos < 1_000_000_000 {
+			let (precision, scale, unit) = if nanos < 1_000 {
+				(0, 1, "ns")
+			} else if nanos < 1_000_000 {
+				(3, 1_000, "µs")
+			} else {
+				(6, 1_000_000, "ms")
+			};
+			write!(f, "{}", nanos / scale)?;
+			write_fraction(f, nanos % scale, precision)?;
+			return write!(f, "{}", unit);
+		}
+
+		let seconds = nanos / 1_000_000_000;
+		let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+		if hours > 0 {
+			write!(f, "{}h{}m", hours, minutes)?;
+		} else if minutes > 0 {
+			write!(f, "{}m", minutes)?;
+		}
+		write!(f, "{}", seconds % 60)?;
+		write_fraction(f, nanos % 1_000_000_000, 9)?;
+		write!(f, "s")
+	}
+}
+
+impl FromStr for GoDuration {
+	type Err = crate::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		GoDuration::parse(s).map_err(crate::Error::InvalidDuration)
+	}
+}
+
+impl From<GoDuration> for Duration {
+	fn from(dur: GoDuration) -> Self {
+		dur.to_duration()
+	}
+}
+
+impl<'de> Deserialize<'de> for GoDuration {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		GoDuration::parse(&String::deserialize(deserializer)?).map_err(DeError::custom)
+	}
+}
+
+impl Serialize for GoDuration {
+	fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		serializer.collect_str(self)
+	}
+}
+
+/// A duration from smallstep's configuration, which may be missing, or may
+/// not be a valid duration at all. Rather than treating a typo like `24hh`
+/// the same as a missing duration (which for a maximum usually means "no
+/// limit"), the raw value is kept so it can be reported.
+///
+/// Invalid durations are written back out exactly as they were read, even if
+/// they weren't a string.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ParsedDuration {
+	/// There was no duration.
+	#[default]
+	Absent,
+	/// A valid duration.
+	Valid(Duration),
+	/// Something that isn't a valid duration, as it was given to us.
+	Invalid(JsonValue),
+}
+
+impl ParsedDuration {
+	/// The duration, if there was a valid one.
+	#[must_use]
+	pub fn valid(&self) -> Option<Duration> {
+		match self {
+			ParsedDuration::Valid(dur) => Some(*dur),
+			_ => None,
+		}
+	}
+
+	/// The raw value, if there was a duration, but it wasn't valid.
+	#[must_use]
+	pub fn invalid(&self) -> Option<&JsonValue> {
+		match self {
+			ParsedDuration::Invalid(raw) => Some(raw),
+			_ => None,
+		}
+	}
+
+	/// If there was no duration at all.
+	#[must_use]
+	pub fn is_absent(&self) -> bool {
+		matches!(self, ParsedDuration::Absent)
+	}
+}
+
+impl From<Option<Duration>> for ParsedDuration {
+	fn from(dur: Option<Duration>) -> Self {
+		dur.map_or(ParsedDuration::Absent, ParsedDuration::Valid)
+	}
+}
+
+impl<'de> Deserialize<'de> for ParsedDuration {
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		Ok(match JsonValue::deserialize(deserializer)? {
+			JsonValue::Null => ParsedDuration::Absent,
+			JsonValue::String(raw) => match GoDuration::parse(&raw) {
+				Ok(dur) => ParsedDuration::Valid(dur.to_duration()),
+				Err(_) => ParsedDuration::Invalid(JsonValue::String(raw)),
+			},
+			other => ParsedDuration::Invalid(other),
+		})
+	}
+}
+
+impl Serialize for ParsedDuration {
+	fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		match self {
+			ParsedDuration::Absent => serializer.serialize_none(),
+			ParsedDuration::Valid(dur) => to_golang_duration(dur, serializer),
+			ParsedDuration::Invalid(raw) => raw.serialize(serializer),
+		}
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use crate::types::{from_golang_duration_opt_strict, StepProvisionerClaims};
+	use proptest::prelude::*;
+
+	#[derive(Debug, Deserialize)]
+	pub struct StrictDuration {
+		#[serde(deserialize_with = "from_golang_duration_opt_strict", default)]
+		pub field: Option<Duration>,
+	}
+
+	#[test]
+	pub fn test_malformed_durations() {
+		let claims = serde_json::from_str::<StepProvisionerClaims>(
+			r#"{"minTLSCertDuration": "5m", "maxTLSCertDuration": "24hh", "defaultTLSCertDuration": 5}"#,
+		)
+		.unwrap();
+		assert_eq!(claims.min_tls_dur.valid(), Some(Duration::minutes(5)));
+		assert_eq!(claims.max_tls_dur.invalid(), Some(&JsonValue::from("24hh")));
+		assert!(claims.min_user_ssh_cert_dur.is_absent());
+		assert_eq!(
+			claims.invalid_durations(),
+			vec![
+				("maxTLSCertDuration", &JsonValue::from("24hh")),
+				("defaultTLSCertDuration", &JsonValue::from(5))
+			]
+		);
+		let written = serde_json::to_value(&claims).unwrap();
+		assert_eq!(written["maxTLSCertDuration"], "24hh");
+		assert_eq!(written["defaultTLSCertDuration"], 5);
+		assert!(written.get("minUserSSHCertDuration").is_none());
+
+		assert!(serde_json::from_str::<StrictDuration>(r#"{"field": "24hh"}"#).is_err());
+		assert!(serde_json::from_str::<StrictDuration>(r#"{"field": 5}"#).is_err());
+		assert_eq!(
+			serde_json::from_str::<StrictDuration>(r#"{"field": "1h"}"#)
+				.unwrap()
+				.field,
+			Some(Duration::hours(1))
+		);
+		assert!(serde_json::from_str::<StrictDuration>(r#"{"field": null}"#)
+			.unwrap()
+			.field
+			.is_none());
+		assert!(serde_json::from_str::<StrictDuration>("{}")
+			.unwrap()
+			.field
+			.is_none());
+	}
+
+	/// The table golang itself tests `time.Duration.String` against.
+	#[test]
+	pub fn test_display_go_duration() {
+		for (nanos, expected) in &[
+			(0, "0s"),
+			(1, "1ns"),
+			(1_100, "1.1µs"),
+			(2_200_000, "2.2ms"),
+			(3_300_000_000, "3.3s"),
+			(245_000_000_000, "4m5s"),
+			(245_001_000_000, "4m5.001s"),
+			(18_367_001_000_000, "5h6m7.001s"),
+			(480_000_000_001, "8m0.000000001s"),
+			(i64::MAX, "2562047h47m16.854775807s"),
+			(i64::MIN, "-2562047h47m16.854775808s"),
+			(-1_500, "-1.5µs"),
+			(28_800_000_000_000, "8h0m0s"),
+		] {
+			assert_eq!(GoDuration::from_nanos(*nanos).to_string(), *expected);
+		}
+	}
+
+	#[test]
+	pub fn test_parse_go_duration() {
+		for (to_parse, nanos) in &[
+			("0", 0),
+			("-0", 0),
+			("5s", 5_000_000_000),
+			("+5s", 5_000_000_000),
+			("1.5h", 5_400_000_000_000),
+			("1h2m3.5s", 3_723_500_000_000),
+			("-1.5µs", -1_500),
+			("1.5μs", 1_500),
+			("3us", 3_000),
+			(".5ms", 500_000),
+			("1.s", 1_000_000_000),
+			("1.0040s", 1_004_000_000),
+			("100.00100s", 100_001_000_000),
+			("0.1234567891s", 123_456_789),
+			("9223372036854775807ns", i64::MAX),
+			("9223372036854775.807us", i64::MAX),
+			("9223372036s854ms775us807ns", i64::MAX),
+			("-9223372036854775808ns", i64::MIN),
+		] {
+			assert_eq!(
+				to_parse.parse::<GoDuration>().unwrap().as_nanos(),
+				*nanos,
+				"{}",
+				to_parse
+			);
+		}
+
+		for to_parse in &[
+			"",
+			"3",
+			"-",
+			"s",
+			".",
+			"-.",
+			".s",
+			"+.s",
+			"1d",
+			"24hh",
+			"1h2",
+			"9223372036854775808ns",
+			"-9223372036854775809ns",
+		] {
+			assert!(
+				matches!(
+					to_parse.parse::<GoDuration>(),
+					Err(crate::Error::InvalidDuration(_))
+				),
+				"{}",
+				to_parse
+			);
+		}
+
+		assert_eq!(
+			serde_json::from_str::<GoDuration>(r#""8h""#).unwrap(),
+			GoDuration::from_nanos(28_800_000_000_000)
+		);
+		assert!(serde_json::from_str::<GoDuration>("8").is_err());
+		assert_eq!(
+			serde_json::to_string(&GoDuration::from_nanos(1_500_000_000)).unwrap(),
+			r#""1.5s""#
+		);
+	}
+
+	/// The nanoseconds in the fraction of a unit with `digits` digits worth
+	/// of nanoseconds, e.g. `"25"` of a millisecond (six digits) is 250,000.
+	fn fraction_nanos(fraction: &str, digits: usize) -> i64 {
+		if fraction.is_empty() {
+			return 0;
+		}
+		format!("{:0<width$}", fraction, width = digits)
+			.parse()
+			.unwrap()
+	}
+
+	/// A fraction as golang writes it, nothing at all if there is none.
+	fn written_fraction(fraction: &str) -> String {
+		if fraction.is_empty() {
+			String::new()
+		} else {
+			format!(".{}", fraction)
+		}
+	}
+
+	proptest! {
+		/// Less than a second golang uses the largest unit that fits, with no
+		/// trailing zeros in the fraction.
+		#[test]
+		fn test_go_duration_displays_sub_second(
+			nanos in 1_i64..1_000,
+			micros in 1_i64..1_000,
+			micros_fraction in "([0-9]{0,2}[1-9])?",
+			millis in 1_i64..1_000,
+			millis_fraction in "([0-9]{0,5}[1-9])?",
+			negative in any::<bool>(),
+		) {
+			let sign = if negative { -1 } else { 1 };
+			let prefix = if negative { "-" } else { "" };
+			for (nanos, expected) in &[
+				(nanos, format!("{}{}ns", prefix, nanos)),
+				(
+					micros * 1_000 + fraction_nanos(&micros_fraction, 3),
+					format!("{}{}{}µs", prefix, micros, written_fraction(&micros_fraction)),
+				),
+				(
+					millis * 1_000_000 + fraction_nanos(&millis_fraction, 6),
+					format!("{}{}{}ms", prefix, millis, written_fraction(&millis_fraction)),
+				),
+			] {
+				let dur = GoDuration::from_nanos(sign * nanos);
+				prop_assert_eq!(&dur.to_string(), expected);
+				prop_assert_eq!(expected.parse::<GoDuration>().unwrap(), dur);
+			}
+		}
+
+		/// A second or more golang writes in hours, minutes, and seconds,
+		/// leaving out hours, then minutes while they're zero.
+		#[test]
+		fn test_go_duration_displays_seconds(
+			hours in 0_i64..2_000_000,
+			minutes in 0_i64..60,
+			seconds in 0_i64..60,
+			fraction in "([0-9]{0,8}[1-9])?",
+			negative in any::<bool>(),
+		) {
+			prop_assume!(hours > 0 || minutes > 0 || seconds > 0);
+			let nanos = ((hours * 60 + minutes) * 60 + seconds) * 1_000_000_000
+				+ fraction_nanos(&fraction, 9);
+			let written = format!("{}{}s", seconds, written_fraction(&fraction));
+			let expected = format!(
+				"{}{}",
+				if negative { "-" } else { "" },
+				if hours > 0 {
+					format!("{}h{}m{}", hours, minutes, written)
+				} else if minutes > 0 {
+					format!("{}m{}", minutes, written)
+				} else {
+					written
+				}
+			);
+			let dur = GoDuration::from_nanos(if negative { -nanos } else { nanos });
+			prop_assert_eq!(&dur.to_string(), &expected);
+			prop_assert_eq!(expected.parse::<GoDuration>().unwrap(), dur);
+		}
+
+		#[test]
+		fn test_go_duration_round_trips(nanos in any::<i64>()) {
+			let dur = GoDuration::from_nanos(nanos);
+			prop_assert_eq!(dur.to_string().parse::<GoDuration>().unwrap(), dur);
+			prop_assert_eq!(
+				GoDuration::from_duration(&dur.to_duration()),
+				Some(dur)
+			);
+		}
+
+		#[test]
+		fn test_go_duration_parses_units(
+			hours in 0_i64..100_000,
+			minutes in 0_i64..60,
+			millis in 0_i64..60_000,
+			micros in 0_i64..1_000,
+			negative in any::<bool>(),
+		) {
+			let to_parse = format!(
+				"{}{}h{}m{}.{:03}s{}us",
+				if negative { "-" } else { "" },
+				hours,
+				minutes,
+				millis / 1_000,
+				millis % 1_000,
+				micros
+			);
+			let nanos = ((hours * 60 + minutes) * 60_000 + millis) * 1_000_000 + micros * 1_000;
+			prop_assert_eq!(
+				to_parse.parse::<GoDuration>().unwrap().as_nanos(),
+				if negative { -nanos } else { nanos }
+			);
+		}
+	}
+}
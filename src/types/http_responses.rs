@@ -1,18 +1,24 @@
 //! A module containing all of the HTTP Responses from a smallstep server.
 
-use crate::{types::StepProvisioner, TinystepClient};
-use color_eyre::Result;
+use crate::Result;
+use crate::{
+	types::{
+		CursorPage, CursorPageStream, CursorPages, CursorPaginator, CursorStream,
+		StepJWKProvisioner, StepProvisioner, StepProvisionerType,
+	},
+	TinystepClient,
+};
 use futures::{
-	future::FutureExt,
+	future::ready,
 	task::{Context, Poll},
-	Stream,
+	Stream, StreamExt,
 };
-use serde::Deserialize;
-use std::{future::Future, pin::Pin};
+use serde::{Deserialize, Serialize};
+use std::{pin::Pin, sync::Arc};
 
 /// The JSON Response from calling:
 /// `https://api.smallstep.com/v1/teams/{team name}/authorities/{authority name}`.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct HostedAuthorityResponse {
 	/// The fingerprint of the root certificate authority.
 	pub fingerprint: String,
@@ -22,29 +28,59 @@ pub struct HostedAuthorityResponse {
 
 /// The JSON Response from calling:
 /// `${smallstep_ca_url}/root/{certificate_fingerprint}`
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepRootResponse {
 	/// The PEM Encoded Certificate authority file.
 	pub ca: String,
 }
 
+/// The JSON Response from calling:
+/// `${smallstep_ca_url}/roots`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepRootsResponse {
+	/// Every PEM Encoded root certificate the authority currently trusts.
+	pub crts: Vec<String>,
+}
+
 /// The JSON Response from calling:
 /// `${smallstep_ca_url}/version`
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepVersionResponse {
-	/// If this server requires client authentication.
-	///
-	/// We don't actually check this right now, and always assume it's true.
-	/// TODO(xxx): can this ever not be true, besides testing?
+	/// If this server requires client authentication, see:
+	/// `TinystepClient::requires_client_authentication`.
 	#[serde(rename = "requireClientAuthentication")]
 	pub require_client_authentication: bool,
 	/// The version the server is running.
 	pub version: String,
 }
 
+/// The JSON body smallstep responds with when a request fails.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepErrorResponse {
+	/// The HTTP status code of the response.
+	pub status: u16,
+	/// A human readable message describing what went wrong.
+	pub message: String,
+}
+
+/// The JSON Response from calling:
+/// `${smallstep_ca_url}/renew`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepSignResponse {
+	/// The PEM Encoded certificate that was issued.
+	pub crt: String,
+	/// The PEM Encoded certificate of the intermediate that issued `crt`.
+	pub ca: String,
+	/// The full PEM Encoded certificate chain, starting with the leaf
+	/// certificate. Older versions of smallstep do not send this, in which
+	/// case it will be empty.
+	#[serde(rename = "certChain", default)]
+	pub cert_chain: Vec<String>,
+}
+
 /// The JSON response from calling:
 /// `${smallstep_ca_url}/health`
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepHealthResponse {
 	/// The status of this smallstep instance.
 	///
@@ -57,7 +93,7 @@ pub struct StepHealthResponse {
 ///
 /// The main difference between this, and `StepPartialProvisionersResponse`
 /// is this is not iterable, and provides the raw `next_cursor`.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepProvisionersResponseRaw {
 	/// The list of provisioners.
 	#[serde(deserialize_with = "crate::types::dynamic_provisioner_list")]
@@ -68,152 +104,353 @@ pub struct StepProvisionersResponseRaw {
 	pub next_cursor: String,
 }
 
+impl CursorPage for StepProvisionersResponseRaw {
+	type Item = StepProvisioner;
+
+	fn items(self) -> Vec<StepProvisioner> {
+		self.provisioners
+	}
+
+	fn next_cursor(&self) -> Option<&str> {
+		if self.next_cursor.is_empty() {
+			None
+		} else {
+			Some(&self.next_cursor)
+		}
+	}
+}
+
 /// The JSON response from calling:
 /// `${smallstep_ca_url}/provisioners`
 ///
 /// This takes a reference to a tinystep client, and provides an `Iterable`
 /// over a `StepProvisioner`.
-pub struct StepProvisionersPaginator<'a> {
-	/// A cnt into the current fetched_last
-	cnt: usize,
-	/// The last fetched item.
-	fetched_last: Option<StepProvisionersResponseRaw>,
-	/// The underlying tinystep client to make requests with.
-	tclient: &'a TinystepClient,
-}
+pub struct StepProvisionersPaginator<'a>(CursorPaginator<'a, StepProvisionersResponseRaw>);
 
 impl<'a> StepProvisionersPaginator<'a> {
 	/// Construct a new paginator for `/provisioners` endpoint.
 	#[must_use]
 	pub fn new(client: &'a TinystepClient) -> StepProvisionersPaginator<'a> {
-		Self {
-			cnt: 0,
-			fetched_last: None,
-			tclient: client,
-		}
+		Self::build(client, None)
 	}
-}
 
-impl<'a> Iterator for StepProvisionersPaginator<'a> {
-	type Item = Result<StepProvisioner>;
+	/// Construct a new paginator for `/provisioners` endpoint, that fetches
+	/// `limit` provisioners per page.
+	#[must_use]
+	pub fn with_limit(client: &'a TinystepClient, limit: u32) -> StepProvisionersPaginator<'a> {
+		Self::build(client, Some(limit))
+	}
 
-	/// Move to the next item inside of a paginator.
-	fn next(&mut self) -> Option<Self::Item> {
-		if self.fetched_last.is_none() {
-			// This is a first fetch
-			let res = crate::api::provisioners_raw(None, self.tclient);
-			if let Err(res_err) = res {
-				return Some(Err(res_err));
+	/// Iterate over whole pages of provisioners, rather than individual
+	/// provisioners.
+	#[must_use]
+	pub fn pages(self) -> CursorPages<'a, StepProvisionersResponseRaw> {
+		self.0.pages()
+	}
+
+	/// Find the provisioner with this name, only fetching pages until it's
+	/// found.
+	///
+	/// # Errors
+	///
+	/// - When fetching a page fails.
+	pub fn find_provisioner(self, name: &str) -> Result<Option<StepProvisioner>> {
+		for provisioner in self {
+			let provisioner = provisioner?;
+			if provisioner.name() == name {
+				return Ok(Some(provisioner));
 			}
-			self.fetched_last = Some(res.unwrap());
 		}
+		Ok(None)
+	}
 
-		let mut page = self.fetched_last.as_ref().unwrap();
-		if page.provisioners.is_empty() {
-			return None;
-		}
-		// We need to go fetch the next page.
-		if self.cnt >= page.provisioners.len() {
-			if page.next_cursor.is_empty() {
-				return None;
-			} else {
-				let res =
-					crate::api::provisioners_raw(Some(page.next_cursor.clone()), self.tclient);
-				if let Err(res_err) = res {
-					return Some(Err(res_err));
+	/// Iterate over only the provisioners of a particular type.
+	pub fn provisioners_of_type(
+		self,
+		typ: StepProvisionerType,
+	) -> impl Iterator<Item = Result<StepProvisioner>> + 'a {
+		self.filter(move |provisioner| {
+			provisioner
+				.as_ref()
+				.map_or(true, |provisioner| provisioner.typ() == typ)
+		})
+	}
+
+	/// Find the JWK provisioner whose key has this key id (`kid`), only
+	/// fetching pages until it's found.
+	///
+	/// # Errors
+	///
+	/// - When fetching a page fails.
+	pub fn find_jwk_by_kid(self, kid: &str) -> Result<Option<StepJWKProvisioner>> {
+		for provisioner in self {
+			if let StepProvisioner::JsonWebKeyProvisioner(jwk) = provisioner? {
+				if jwk.key.kid.as_deref() == Some(kid) {
+					return Ok(Some(jwk));
 				}
-				self.fetched_last = Some(res.unwrap());
-				self.cnt = 0;
-				page = self.fetched_last.as_ref().unwrap();
 			}
 		}
-		// We may have fetched another page, check for emptyness again.
-		if page.provisioners.is_empty() {
-			return None;
-		}
+		Ok(None)
+	}
+
+	/// Build a paginator with an optional page size.
+	fn build(client: &'a TinystepClient, limit: Option<u32>) -> StepProvisionersPaginator<'a> {
+		Self(CursorPaginator::new(move |cursor| {
+			crate::api::provisioners_raw(cursor, limit, client)
+		}))
+	}
+}
+
+impl<'a> Iterator for StepProvisionersPaginator<'a> {
+	type Item = Result<StepProvisioner>;
 
-		// At this point we're guaranteed to be safe for indexing.
-		Some(Ok(page.provisioners.get(self.cnt).unwrap().clone()))
+	/// Move to the next item inside of a paginator.
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()
 	}
 }
 
 /// The JSON response from calling:
 /// `${smallstep_ca_url}/provisioners`
 ///
-/// This takes a reference to a tinystep client, and provides an `futures::Stream`
-/// over a `StepProvisioner`.
-pub struct StepProvisionersAsyncPaginator<'fetch, 'client: 'fetch> {
-	/// A cnt into the current fetched_last
-	cnt: usize,
-	/// The last fetched item.
-	fetched_last: Option<StepProvisionersResponseRaw>,
-	/// An optional currently pending fetch.
-	current_pending_fetch:
-		Option<Pin<Box<dyn Future<Output = Result<StepProvisionersResponseRaw>> + 'fetch>>>,
-	/// The underlying tinystep client to make requests with.
-	tclient: &'client TinystepClient,
-}
+/// This either borrows, or owns a tinystep client, and provides a
+/// `futures::Stream` over a `StepProvisioner`. The stream is `Send`, and when
+/// it owns its client (see: `new_owned`) it's `'static`, so it can be moved
+/// into `tokio::spawn`.
+pub struct StepProvisionersAsyncPaginator<'a>(CursorStream<'a, StepProvisionersResponseRaw>);
 
-impl<'fetch, 'client: 'fetch> StepProvisionersAsyncPaginator<'fetch, 'client> {
+impl<'a> StepProvisionersAsyncPaginator<'a> {
 	/// Construct a new async paginator for `/provisioners` endpoint.
 	#[must_use]
-	pub fn new(client: &'client TinystepClient) -> StepProvisionersAsyncPaginator<'fetch, 'client> {
-		Self {
-			cnt: 0,
-			fetched_last: None,
-			current_pending_fetch: None,
-			tclient: client,
-		}
+	pub fn new(client: &'a TinystepClient) -> StepProvisionersAsyncPaginator<'a> {
+		Self::build(client, None)
 	}
-}
 
-impl<'fetch, 'client: 'fetch> Stream for StepProvisionersAsyncPaginator<'fetch, 'client> {
-	type Item = Result<StepProvisioner>;
+	/// Construct a new async paginator for `/provisioners` endpoint, that
+	/// fetches `limit` provisioners per page.
+	#[must_use]
+	pub fn with_limit(
+		client: &'a TinystepClient,
+		limit: u32,
+	) -> StepProvisionersAsyncPaginator<'a> {
+		Self::build(client, Some(limit))
+	}
+
+	/// Stream whole pages of provisioners, rather than individual
+	/// provisioners.
+	#[must_use]
+	pub fn pages(self) -> CursorPageStream<'a, StepProvisionersResponseRaw> {
+		self.0.pages()
+	}
 
-	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-		let this = unsafe { self.get_unchecked_mut() };
-		if this.current_pending_fetch.is_none() && this.fetched_last.is_none() {
-			this.current_pending_fetch =
-				Some(crate::api::provisioners_raw_async(None, this.tclient).boxed_local());
+	/// Find the provisioner with this name, only fetching pages until it's
+	/// found.
+	///
+	/// # Errors
+	///
+	/// - When fetching a page fails.
+	pub async fn find_provisioner(mut self, name: &str) -> Result<Option<StepProvisioner>> {
+		while let Some(provisioner) = self.next().await {
+			let provisioner = provisioner?;
+			if provisioner.name() == name {
+				return Ok(Some(provisioner));
+			}
 		}
+		Ok(None)
+	}
 
-		if this.current_pending_fetch.is_some() {
-			// Check if current_pending_fetch is done.
-			match this.current_pending_fetch.as_mut().unwrap().poll_unpin(cx) {
-				Poll::Pending => {
-					return Poll::Pending;
-				}
-				Poll::Ready(val) => {
-					if let Err(err_value) = val {
-						return Poll::Ready(Some(Err(err_value)));
-					} else {
-						this.fetched_last = Some(val.unwrap());
-					}
+	/// Stream only the provisioners of a particular type.
+	pub fn provisioners_of_type(
+		self,
+		typ: StepProvisionerType,
+	) -> impl Stream<Item = Result<StepProvisioner>> + Send + 'a {
+		self.filter(move |provisioner| {
+			ready(
+				provisioner
+					.as_ref()
+					.map_or(true, |provisioner| provisioner.typ() == typ),
+			)
+		})
+	}
+
+	/// Find the JWK provisioner whose key has this key id (`kid`), only
+	/// fetching pages until it's found.
+	///
+	/// # Errors
+	///
+	/// - When fetching a page fails.
+	pub async fn find_jwk_by_kid(mut self, kid: &str) -> Result<Option<StepJWKProvisioner>> {
+		while let Some(provisioner) = self.next().await {
+			if let StepProvisioner::JsonWebKeyProvisioner(jwk) = provisioner? {
+				if jwk.key.kid.as_deref() == Some(kid) {
+					return Ok(Some(jwk));
 				}
 			}
-			this.current_pending_fetch = None;
 		}
+		Ok(None)
+	}
 
-		let page = this.fetched_last.as_ref().unwrap();
-		if page.provisioners.is_empty() {
-			return Poll::Ready(None);
-		}
-		if this.cnt >= page.provisioners.len() {
-			if page.next_cursor.is_empty() {
-				return Poll::Ready(None);
-			} else {
-				this.current_pending_fetch = Some(
-					crate::api::provisioners_raw_async(
-						Some(page.next_cursor.clone()),
-						this.tclient,
-					)
-					.boxed_local(),
-				);
-				this.cnt = 0;
-				return Poll::Pending;
+	/// Build a paginator with an optional page size.
+	fn build(client: &'a TinystepClient, limit: Option<u32>) -> StepProvisionersAsyncPaginator<'a> {
+		Self(CursorStream::from_async(move |cursor| {
+			crate::api::provisioners_raw_async(cursor, limit, client)
+		}))
+	}
+}
+
+impl StepProvisionersAsyncPaginator<'static> {
+	/// Construct a new async paginator for `/provisioners` endpoint, that
+	/// keeps its own handle to the client, rather than borrowing it.
+	#[must_use]
+	pub fn new_owned(client: Arc<TinystepClient>) -> StepProvisionersAsyncPaginator<'static> {
+		Self::build_owned(client, None)
+	}
+
+	/// Construct a new async paginator for `/provisioners` endpoint, that
+	/// keeps its own handle to the client, and fetches `limit` provisioners
+	/// per page.
+	#[must_use]
+	pub fn owned_with_limit(
+		client: Arc<TinystepClient>,
+		limit: u32,
+	) -> StepProvisionersAsyncPaginator<'static> {
+		Self::build_owned(client, Some(limit))
+	}
+
+	/// Build an owned paginator with an optional page size.
+	fn build_owned(
+		client: Arc<TinystepClient>,
+		limit: Option<u32>,
+	) -> StepProvisionersAsyncPaginator<'static> {
+		Self(CursorStream::from_async(move |cursor| {
+			let client = client.clone();
+			async move { crate::api::provisioners_raw_async(cursor, limit, &client).await }
+		}))
+	}
+}
+
+impl<'a> Stream for StepProvisionersAsyncPaginator<'a> {
+	type Item = Result<StepProvisioner>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		Pin::new(&mut self.0).poll_next(cx)
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use futures::executor::block_on;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	/// Three pages of provisioners, counting how many have been fetched.
+	fn fetch(cursor: Option<String>, fetches: &AtomicUsize) -> Result<StepProvisionersResponseRaw> {
+		fetches.fetch_add(1, Ordering::SeqCst);
+		let page = match cursor.as_deref() {
+			None => {
+				r#"{"provisioners": [
+					{"type": "JWK", "name": "first", "key": {"kty": "EC", "kid": "a"}},
+					{"type": "AWS", "name": "aws", "accounts": [], "disableCustomSANs": false, "disableTrustOnFirstUse": false}
+				], "nextCursor": "b"}"#
 			}
-		}
+			Some("b") => {
+				r#"{"provisioners": [
+					{"type": "JWK", "name": "second", "key": {"kty": "EC", "kid": "b"}}
+				], "nextCursor": "c"}"#
+			}
+			_ => {
+				r#"{"provisioners": [
+					{"type": "JWK", "name": "third", "key": {"kty": "EC", "kid": "c"}}
+				], "nextCursor": ""}"#
+			}
+		};
+		Ok(serde_json::from_str(page).unwrap())
+	}
+
+	fn paginator(fetches: &AtomicUsize) -> StepProvisionersPaginator<'_> {
+		StepProvisionersPaginator(CursorPaginator::new(move |cursor| fetch(cursor, fetches)))
+	}
+
+	fn async_paginator(fetches: &AtomicUsize) -> StepProvisionersAsyncPaginator<'_> {
+		StepProvisionersAsyncPaginator(CursorStream::from_async(move |cursor| {
+			ready(fetch(cursor, fetches))
+		}))
+	}
+
+	#[test]
+	pub fn test_find_provisioner() {
+		let fetches = AtomicUsize::new(0);
+		let found = paginator(&fetches).find_provisioner("second").unwrap();
+		assert_eq!(found.unwrap().name(), "second");
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 2);
+		assert!(paginator(&fetches)
+			.find_provisioner("missing")
+			.unwrap()
+			.is_none());
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 3);
+
+		let found = block_on(async_paginator(&fetches).find_provisioner("second")).unwrap();
+		assert_eq!(found.unwrap().name(), "second");
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 2);
+		assert!(
+			block_on(async_paginator(&fetches).find_provisioner("missing"))
+				.unwrap()
+				.is_none()
+		);
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 3);
+	}
+
+	#[test]
+	pub fn test_find_jwk_by_kid() {
+		let fetches = AtomicUsize::new(0);
+		let found = paginator(&fetches).find_jwk_by_kid("a").unwrap();
+		assert_eq!(found.unwrap().name, "first");
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 1);
+		let found = paginator(&fetches).find_jwk_by_kid("c").unwrap();
+		assert_eq!(found.unwrap().name, "third");
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 3);
+
+		let found = block_on(async_paginator(&fetches).find_jwk_by_kid("a")).unwrap();
+		assert_eq!(found.unwrap().name, "first");
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 1);
+		assert!(
+			block_on(async_paginator(&fetches).find_jwk_by_kid("missing"))
+				.unwrap()
+				.is_none()
+		);
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 3);
+	}
+
+	#[test]
+	pub fn test_provisioners_of_type() {
+		let fetches = AtomicUsize::new(0);
+		let names = paginator(&fetches)
+			.provisioners_of_type(StepProvisionerType::JsonWebKey)
+			.map(|provisioner| provisioner.unwrap().name().to_owned())
+			.collect::<Vec<_>>();
+		assert_eq!(names, vec!["first", "second", "third"]);
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 3);
+		let aws = paginator(&fetches)
+			.provisioners_of_type(StepProvisionerType::AmazonWebServices)
+			.next();
+		assert_eq!(aws.unwrap().unwrap().name(), "aws");
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 1);
 
-		Poll::Ready(Some(Ok(page.provisioners.get(this.cnt).unwrap().clone())))
+		let names = block_on(
+			async_paginator(&fetches)
+				.provisioners_of_type(StepProvisionerType::JsonWebKey)
+				.map(|provisioner| provisioner.unwrap().name().to_owned())
+				.collect::<Vec<_>>(),
+		);
+		assert_eq!(names, vec!["first", "second", "third"]);
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 3);
+		let aws = block_on(
+			async_paginator(&fetches)
+				.provisioners_of_type(StepProvisionerType::AmazonWebServices)
+				.boxed()
+				.next(),
+		);
+		assert_eq!(aws.unwrap().unwrap().name(), "aws");
+		assert_eq!(fetches.swap(0, Ordering::SeqCst), 1);
 	}
 }
@@ -3,9 +3,15 @@
 //! don't need to depend on the inner types.
 
 pub mod custom_de;
+pub mod custom_ser;
+pub mod duration;
 pub mod http_responses;
+pub mod pagination;
 pub mod provisioners;
 
 pub use custom_de::*;
+pub use custom_ser::*;
+pub use duration::*;
 pub use http_responses::*;
+pub use pagination::*;
 pub use provisioners::*;
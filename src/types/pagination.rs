@@ -0,0 +1,304 @@
+//! Generic iteration over smallstep endpoints that paginate with a cursor.
+//!
+//! Smallstep paginates every list endpoint the same way, a page contains some
+//! items, and a cursor for the next page which is empty on the last page. Any
+//! page type implementing `CursorPage` can be iterated with a
+//! `CursorPaginator`, or streamed with a `CursorStream`, given a function that
+//! fetches a page by its cursor.
+
+use crate::Result;
+use futures::{
+	future::{BoxFuture, FutureExt},
+	task::{Context, Poll},
+	Stream,
+};
+use std::pin::Pin;
+
+/// A single page of results from a cursor paginated endpoint.
+pub trait CursorPage {
+	/// The type of item in a page.
+	type Item;
+
+	/// Take the items out of this page.
+	fn items(self) -> Vec<Self::Item>;
+
+	/// The cursor for the next page, or `None` if this is the last page.
+	fn next_cursor(&self) -> Option<&str>;
+}
+
+/// Fetches a page given its cursor, `None` being the first page.
+type PageFetch<'a, P> = Box<dyn Fn(Option<String>) -> Result<P> + Send + 'a>;
+
+/// Fetches a page asynchronously given its cursor, `None` being the first
+/// page.
+type AsyncPageFetch<'a, P> = Box<dyn Fn(Option<String>) -> BoxFuture<'a, Result<P>> + Send + 'a>;
+
+/// An `Iterator` over every item of a cursor paginated endpoint, fetching
+/// pages only as they're needed.
+///
+/// If fetching a page fails the error is returned, and calling `next` again
+/// retries fetching that same page.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use tinystep::{api, types::CursorPaginator, TinystepClient};
+/// # let client = TinystepClient::new_from_hosted("bluestone", None).unwrap();
+/// let provisioners = CursorPaginator::new(|cursor| api::provisioners_raw(cursor, None, &client));
+/// for provisioner in provisioners {
+///   println!("{:?}", provisioner.unwrap());
+/// }
+/// ```
+pub struct CursorPaginator<'a, P: CursorPage> {
+	/// Fetches a page.
+	fetch_page: PageFetch<'a, P>,
+	/// The cursor of the next page to fetch.
+	next_cursor: Option<String>,
+	/// If we've fetched the last page.
+	done: bool,
+	/// The items of the current page we haven't returned yet.
+	current: std::vec::IntoIter<P::Item>,
+}
+
+impl<'a, P: CursorPage> CursorPaginator<'a, P> {
+	/// Construct a new paginator, which calls `fetch_page` with the cursor of
+	/// each page it needs.
+	pub fn new(fetch_page: impl Fn(Option<String>) -> Result<P> + Send + 'a) -> Self {
+		Self {
+			fetch_page: Box::new(fetch_page),
+			next_cursor: None,
+			done: false,
+			current: Vec::new().into_iter(),
+		}
+	}
+
+	/// Iterate over whole pages rather than items. Any items left over from a
+	/// page already fetched by `next` are skipped.
+	#[must_use]
+	pub fn pages(self) -> CursorPages<'a, P> {
+		CursorPages(self)
+	}
+
+	/// Fetch the next page, unless we've already fetched the last one.
+	fn next_page(&mut self) -> Option<Result<P>> {
+		if self.done {
+			return None;
+		}
+		Some((self.fetch_page)(self.next_cursor.clone()).inspect(|page| {
+			self.next_cursor = page.next_cursor().map(ToOwned::to_owned);
+			self.done = self.next_cursor.is_none();
+		}))
+	}
+}
+
+impl<'a, P: CursorPage> Iterator for CursorPaginator<'a, P> {
+	type Item = Result<P::Item>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some(item) = self.current.next() {
+				return Some(Ok(item));
+			}
+			match self.next_page()? {
+				Ok(page) => self.current = page.items().into_iter(),
+				Err(err) => return Some(Err(err)),
+			}
+		}
+	}
+}
+
+/// An `Iterator` over every page of a cursor paginated endpoint, see:
+/// `CursorPaginator::pages`.
+pub struct CursorPages<'a, P: CursorPage>(CursorPaginator<'a, P>);
+
+impl<'a, P: CursorPage> Iterator for CursorPages<'a, P> {
+	type Item = Result<P>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next_page()
+	}
+}
+
+/// A `futures::Stream` over every item of a cursor paginated endpoint,
+/// fetching pages only as they're needed. The stream is `Send` so long as the
+/// items are, and `'static` when `fetch_page` owns everything it needs.
+///
+/// If fetching a page fails the error is returned, and polling again
+/// retries fetching that same page.
+pub struct CursorStream<'a, P: CursorPage> {
+	/// Fetches a page.
+	fetch_page: AsyncPageFetch<'a, P>,
+	/// A currently pending fetch.
+	pending_fetch: Option<BoxFuture<'a, Result<P>>>,
+	/// The cursor of the next page to fetch.
+	next_cursor: Option<String>,
+	/// If we've fetched the last page.
+	done: bool,
+	/// The items of the current page we haven't returned yet.
+	current: std::vec::IntoIter<P::Item>,
+}
+
+// Nothing in the stream is ever pinned in place, the pending fetch is already
+// boxed.
+impl<'a, P: CursorPage> Unpin for CursorStream<'a, P> {}
+
+impl<'a, P: CursorPage> CursorStream<'a, P> {
+	/// Construct a new stream, which calls `fetch_page` with the cursor of
+	/// each page it needs.
+	pub fn new(
+		fetch_page: impl Fn(Option<String>) -> BoxFuture<'a, Result<P>> + Send + 'a,
+	) -> Self {
+		Self {
+			fetch_page: Box::new(fetch_page),
+			pending_fetch: None,
+			next_cursor: None,
+			done: false,
+			current: Vec::new().into_iter(),
+		}
+	}
+
+	/// Stream whole pages rather than items. Any items left over from a page
+	/// already fetched by polling this stream are skipped.
+	#[must_use]
+	pub fn pages(self) -> CursorPageStream<'a, P> {
+		CursorPageStream(self)
+	}
+
+	/// Poll fetching the next page, starting the fetch if needed.
+	fn poll_next_page(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<P>>> {
+		if self.pending_fetch.is_none() {
+			if self.done {
+				return Poll::Ready(None);
+			}
+			self.pending_fetch = Some((self.fetch_page)(self.next_cursor.clone()));
+		}
+		let fetched = match self
+			.pending_fetch
+			.as_mut()
+			.map(|fetch| fetch.poll_unpin(cx))
+		{
+			Some(Poll::Ready(fetched)) => fetched,
+			_ => return Poll::Pending,
+		};
+		self.pending_fetch = None;
+		Poll::Ready(Some(fetched.inspect(|page| {
+			self.next_cursor = page.next_cursor().map(ToOwned::to_owned);
+			self.done = self.next_cursor.is_none();
+		})))
+	}
+}
+
+impl<'a, P: CursorPage> CursorStream<'a, P>
+where
+	P: Send + 'a,
+{
+	/// Construct a new stream from an `async` function, or closure that
+	/// fetches a page.
+	pub fn from_async<F, Fut>(fetch_page: F) -> Self
+	where
+		F: Fn(Option<String>) -> Fut + Send + 'a,
+		Fut: std::future::Future<Output = Result<P>> + Send + 'a,
+	{
+		Self::new(move |cursor| fetch_page(cursor).boxed())
+	}
+}
+
+impl<'a, P: CursorPage> Stream for CursorStream<'a, P> {
+	type Item = Result<P::Item>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		let this = self.get_mut();
+		loop {
+			if let Some(item) = this.current.next() {
+				return Poll::Ready(Some(Ok(item)));
+			}
+			match this.poll_next_page(cx) {
+				Poll::Pending => return Poll::Pending,
+				Poll::Ready(None) => return Poll::Ready(None),
+				Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+				Poll::Ready(Some(Ok(page))) => this.current = page.items().into_iter(),
+			}
+		}
+	}
+}
+
+/// A `futures::Stream` over every page of a cursor paginated endpoint, see:
+/// `CursorStream::pages`.
+pub struct CursorPageStream<'a, P: CursorPage>(CursorStream<'a, P>);
+
+impl<'a, P: CursorPage> Stream for CursorPageStream<'a, P> {
+	type Item = Result<P>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		self.get_mut().0.poll_next_page(cx)
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use futures::{executor::block_on, future::ready, StreamExt};
+
+	struct NumberPage {
+		numbers: Vec<u32>,
+		next_cursor: Option<String>,
+	}
+
+	impl CursorPage for NumberPage {
+		type Item = u32;
+
+		fn items(self) -> Vec<u32> {
+			self.numbers
+		}
+
+		fn next_cursor(&self) -> Option<&str> {
+			self.next_cursor.as_deref()
+		}
+	}
+
+	/// Three pages, the middle one being empty.
+	fn fetch(cursor: Option<String>) -> Result<NumberPage> {
+		Ok(match cursor.as_deref() {
+			None => NumberPage {
+				numbers: vec![1, 2],
+				next_cursor: Some("b".to_owned()),
+			},
+			Some("b") => NumberPage {
+				numbers: vec![],
+				next_cursor: Some("c".to_owned()),
+			},
+			_ => NumberPage {
+				numbers: vec![3],
+				next_cursor: None,
+			},
+		})
+	}
+
+	#[test]
+	pub fn test_paginates() {
+		let numbers = CursorPaginator::new(fetch)
+			.collect::<Result<Vec<_>>>()
+			.unwrap();
+		assert_eq!(numbers, vec![1, 2, 3]);
+
+		let numbers =
+			block_on(CursorStream::from_async(|cursor| ready(fetch(cursor))).collect::<Vec<_>>());
+		assert_eq!(
+			numbers.into_iter().collect::<Result<Vec<_>>>().unwrap(),
+			vec![1, 2, 3]
+		);
+
+		let page_sizes = CursorPaginator::new(fetch)
+			.pages()
+			.map(|page| page.unwrap().numbers.len())
+			.collect::<Vec<_>>();
+		assert_eq!(page_sizes, vec![2, 0, 1]);
+		let page_sizes = block_on(
+			CursorStream::from_async(|cursor| ready(fetch(cursor)))
+				.pages()
+				.map(|page| page.unwrap().numbers.len())
+				.collect::<Vec<_>>(),
+		);
+		assert_eq!(page_sizes, vec![2, 0, 1]);
+	}
+}
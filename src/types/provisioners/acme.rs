@@ -2,11 +2,11 @@
 //! pretty large types so we split it to it's own module for readability sake.
 
 use crate::types::StepProvisionerType;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Provision certificates using ACME to provide authentication so we know
 /// which certs to issue. <https://smallstep.com/docs/step-ca/configuration#acme>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepAcmeProvisioner {
 	/// The type of this provisioner, will always be:
 	/// `StepProvisionerType::Acme`.
@@ -17,6 +17,13 @@ pub struct StepAcmeProvisioner {
 	/// An override of "Claims" for this provisioner. This will allow the
 	/// provisioner to manually specify the default/min/max tls certificate
 	/// issue time if specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub claims: Option<super::StepProvisionerClaims>,
+	/// The templating options for certificates issued by this provisioner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
 }
@@ -1,14 +1,13 @@
 //! All of the types for an AWS Provisioner, these are split out because they're
 //! pretty large types so we split it to it's own module for readability sake.
 
-use crate::types::StepProvisionerType;
-use chrono::Duration;
-use serde::Deserialize;
+use crate::types::{ParsedDuration, StepProvisionerType};
+use serde::{Deserialize, Serialize};
 
 /// Provision certificates using a AWS Instance Identity for authentication
 /// to know which certs can be issued, and which instance is doing them.
 /// <https://smallstep.com/docs/step-ca/configuration#cloud-provisioners>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepAWSProvisioner {
 	/// The type of this provisioner, will always be:
 	/// `StepProvisionerType::AmazonWebServices`.
@@ -41,13 +40,20 @@ pub struct StepAWSProvisioner {
 	/// An optional maximum duration of an instance to grant a certificate.
 	#[serde(
 		rename = "instanceAge",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub instance_age: Option<Duration>,
+	pub instance_age: ParsedDuration,
 	/// An override of "Claims" for this provisioner. This will allow the
 	/// provisioner to manually specify the default/min/max tls certificate
 	/// issue time if specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub claims: Option<super::StepProvisionerClaims>,
+	/// The templating options for certificates issued by this provisioner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
 }
@@ -2,12 +2,12 @@
 //! pretty large types so we split it to it's own module for readability sake.
 
 use crate::types::StepProvisionerType;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Provision certificates using a Azure Instance Identity for authentication
 /// to know which certs can be issued, and which instance is doing them.
 /// <https://smallstep.com/docs/step-ca/configuration#cloud-provisioners>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepAzureProvisioner {
 	/// The type of this provisioner, will always be:
 	/// `StepProvisionerType::Azure`.
@@ -25,7 +25,7 @@ pub struct StepAzureProvisioner {
 	pub resource_groups: Vec<String>,
 	/// An audience for Azure AD, defaults to: <https://management.azure.com/>,
 	/// if not specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub audience: Option<String>,
 	/// By default Custom SANs are allowed for instances, if this is set to true
 	/// Custom SANs will be disabled, and instances will only be able to issue
@@ -46,6 +46,13 @@ pub struct StepAzureProvisioner {
 	/// An override of "Claims" for this provisioner. This will allow the
 	/// provisioner to manually specify the default/min/max tls certificate
 	/// issue time if specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub claims: Option<super::StepProvisionerClaims>,
+	/// The templating options for certificates issued by this provisioner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
 }
@@ -2,7 +2,7 @@
 //! pretty large types so we split it to it's own module for readability sake.
 
 use crate::types::StepProvisionerType;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// The "Raw" serialized JSON Web Key. PLEASE NOTE: these values are raw
 /// values of a JWK. JWKs are notoriously full of footguns, and these
@@ -10,75 +10,75 @@ use serde::Deserialize;
 /// from `SmallStep`, but please make sure you use these carefully.
 ///
 /// <https://tools.ietf.org/html/rfc7517>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepJoseRawWebKey {
 	/// The use of this JSON Web Key.
-	#[serde(rename = "use", default)]
+	#[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
 	pub us: Option<String>,
 	/// The Key Type of this JSON Web Key.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub kty: Option<String>,
 	/// The JWK value of "kid".
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub kid: Option<String>,
 	/// The Curve this JSON Web Key is using.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub crv: Option<String>,
 	/// The algorithim header of this JWK.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub alg: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub k: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub x: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub y: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub n: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub e: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub d: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub p: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub q: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub dp: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub dq: Option<String>,
 	/// Raw value of certain key algorithims that can be populated.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub qi: Option<String>,
 	/// An optional certificate chain for the JWK.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub x5c: Option<Vec<String>>,
 	/// An optional certificate url for the JWK.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub x5u: Option<String>,
 	/// x5t parameters are base64url-encoded SHA thumbprints
 	/// See RFC 7517, Section 4.8, <https://tools.ietf.org/html/rfc7517#section-4.8>
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub x5t: Option<String>,
 	/// x5t parameters are base64url-encoded SHA thumbprints
 	/// See RFC 7517, Section 4.8, <https://tools.ietf.org/html/rfc7517#section-4.8>
-	#[serde(rename = "x5t#S256", default)]
+	#[serde(rename = "x5t#S256", default, skip_serializing_if = "Option::is_none")]
 	pub x5t_sha256: Option<String>,
 }
 
 /// Provision certificates using JWKs to provide authentication so we know
 /// which certs to issue. <https://smallstep.com/docs/step-ca/configuration#jwk>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepJWKProvisioner {
 	/// The type of this provisioner, will always be:
 	/// `StepProvisionerType::JsonWebKey`.
@@ -95,11 +95,22 @@ pub struct StepJWKProvisioner {
 	/// An optional encrypted private key used to sign tokens. Is encrypted
 	/// according to the [JSON Web Encryption](https://tools.ietf.org/html/rfc7516)
 	/// standard if present.
-	#[serde(rename = "encryptedKey", default)]
+	#[serde(
+		rename = "encryptedKey",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
 	pub encrypted_key: Option<String>,
 	/// An override of "Claims" for this provisioner. This will allow the
 	/// provisioner to manually specify the default/min/max tls certificate
 	/// issue time if specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub claims: Option<super::StepProvisionerClaims>,
+	/// The templating options for certificates issued by this provisioner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
 }
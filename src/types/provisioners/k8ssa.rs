@@ -3,12 +3,12 @@
 //! module for readability sake.
 
 use crate::types::StepProvisionerType;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Provision certificates using Kubernetes Service Account to provide
 /// authentication so we know which certs to issue.
 /// <https://smallstep.com/docs/step-ca/configuration#k8ssa-kubernetes-service-account>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepK8SSAProvisioner {
 	/// The type of this provisioner, will always be:
 	/// `StepProvisionerType::KubernetesServiceAccount`.
@@ -19,11 +19,18 @@ pub struct StepK8SSAProvisioner {
 	/// An override of "Claims" for this provisioner. This will allow the
 	/// provisioner to manually specify the default/min/max tls certificate
 	/// issue time if specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub claims: Option<super::StepProvisionerClaims>,
 	/// This is techincally ***mandatory*** for now. One day it may become
 	/// optional, however this is not yet implemented. When provided is a base64
 	/// encoded list of public keys to validate the kubernetes service account.
-	#[serde(rename = "publicKeys")]
+	#[serde(rename = "publicKeys", skip_serializing_if = "Option::is_none")]
 	pub public_keys: Option<String>,
+	/// The templating options for certificates issued by this provisioner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
 }
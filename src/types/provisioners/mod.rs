@@ -2,8 +2,8 @@
 //! their own module, since they are generally very large objects which can
 //! make it hard to read if not split out.
 
-use chrono::Duration;
-use serde::Deserialize;
+use crate::types::ParsedDuration;
+use serde::{de::Error as DeError, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value as JsonValue;
 
 pub mod acme;
@@ -12,7 +12,9 @@ pub mod azure;
 pub mod gcp;
 pub mod jwk;
 pub mod k8ssa;
+pub mod nebula;
 pub mod oidc;
+pub mod scep;
 pub mod sshpop;
 pub mod x5c;
 
@@ -22,14 +24,16 @@ pub use azure::*;
 pub use gcp::*;
 pub use jwk::*;
 pub use k8ssa::*;
+pub use nebula::*;
 pub use oidc::*;
+pub use scep::*;
 pub use sshpop::*;
 pub use x5c::*;
 
 /// Represents all of the provisioner types for a smallstep instance.
 /// This is effectively an enum that wraps all of the possible values of
 /// the `type` field from a Provisioner Configuration.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum StepProvisionerType {
 	/// A Provisioner using a JWK for identities.
 	///
@@ -77,10 +81,26 @@ pub enum StepProvisionerType {
 	/// <https://smallstep.com/docs/step-ca/configuration#sshpop-ssh-certificate>
 	#[serde(rename = "SSHPOP")]
 	SshKeypair,
+	/// A Provisioner using the Simple Certificate Enrollment Protocol.
+	///
+	/// <https://smallstep.com/docs/step-ca/provisioners#scep>
+	#[serde(rename = "SCEP")]
+	Scep,
+	/// A Provisioner using a certificate from a Nebula overlay network for
+	/// identity.
+	///
+	/// <https://smallstep.com/docs/step-ca/provisioners#nebula>
+	#[serde(rename = "Nebula")]
+	Nebula,
+	/// A type of provisioner tinystep doesn't know about (yet), see:
+	/// `StepProvisioner::Unknown`. This is never deserialized, so a particular
+	/// type of provisioner can't be read from a provisioner of another type.
+	#[serde(skip_deserializing)]
+	Unknown,
 }
 
 impl std::str::FromStr for StepProvisionerType {
-	type Err = color_eyre::Report;
+	type Err = crate::Error;
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
 		match s {
@@ -93,10 +113,9 @@ impl std::str::FromStr for StepProvisionerType {
 			"X5C" => Ok(StepProvisionerType::X509CertBundle),
 			"K8sSA" => Ok(StepProvisionerType::KubernetesServiceAccount),
 			"SSHPOP" => Ok(StepProvisionerType::SshKeypair),
-			_ => Err(color_eyre::eyre::eyre!(
-				"Failed to find provisioner type: {:?}",
-				s
-			)),
+			"SCEP" => Ok(StepProvisionerType::Scep),
+			"Nebula" => Ok(StepProvisionerType::Nebula),
+			_ => Err(crate::Error::UnknownProvisionerType(s.to_owned())),
 		}
 	}
 }
@@ -104,112 +123,163 @@ impl std::str::FromStr for StepProvisionerType {
 /// Represents the "claims" part of a provisioner, which contains generic
 /// claims for the actual certificates/keys issued by this provisioner.
 /// These are things like min/max/default durations.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepProvisionerClaims {
 	/// An optional minimum duration for TLS Certificates for this provisioner.
 	#[serde(
 		rename = "minTLSCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub min_tls_dur: Option<Duration>,
+	pub min_tls_dur: ParsedDuration,
 	/// An optional maximum duration for TLS Certificates for the provisioner.
 	#[serde(
 		rename = "maxTLSCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub max_tls_dur: Option<Duration>,
+	pub max_tls_dur: ParsedDuration,
 	/// The optional default duration for TLS Certificates for the provisioner.
 	#[serde(
 		rename = "defaultTLSCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub default_tls_dur: Option<Duration>,
+	pub default_tls_dur: ParsedDuration,
 	/// An optional status of whether or not renewals are disabled.
 	///
 	/// If not specified assume renewal's aren't disabled.
-	#[serde(rename = "disableRenewal", default)]
+	#[serde(
+		rename = "disableRenewal",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
 	pub disable_renewal: Option<bool>,
 	/// An optional minimum duration for SSH User Certs issued.
 	#[serde(
 		rename = "minUserSSHCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub min_user_ssh_cert_dur: Option<Duration>,
+	pub min_user_ssh_cert_dur: ParsedDuration,
 	/// An optional maximum duration for SSH User Certs issued.
 	#[serde(
 		rename = "maxUserSSHCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub max_user_ssh_cert_dur: Option<Duration>,
+	pub max_user_ssh_cert_dur: ParsedDuration,
 	/// The optional default duration for SSH User Certs issued.
 	#[serde(
 		rename = "defaultUserSSHCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub default_user_ssh_cert_duration: Option<Duration>,
+	pub default_user_ssh_cert_duration: ParsedDuration,
 	/// An optional minimum duration for SSH Host Certs issued.
 	#[serde(
 		rename = "minHostSSHCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub min_host_ssh_cert_duration: Option<Duration>,
+	pub min_host_ssh_cert_duration: ParsedDuration,
 	/// An optional maximum duration for SSH Host Certs issued.
 	#[serde(
 		rename = "maxHostSSHCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub max_host_ssh_cert_duration: Option<Duration>,
+	pub max_host_ssh_cert_duration: ParsedDuration,
 	/// The optional default duration for SSH Host Certs issued.
 	#[serde(
 		rename = "defaultHostSSHCertDuration",
-		deserialize_with = "crate::types::from_golang_duration_opt",
+		skip_serializing_if = "ParsedDuration::is_absent",
 		default
 	)]
-	pub default_host_ssh_cert_duration: Option<Duration>,
+	pub default_host_ssh_cert_duration: ParsedDuration,
 	/// An option that determines if SSH CA has been abled.
 	///
 	/// If not specified assume it does not exist.
-	#[serde(rename = "enableSSHCA", default)]
+	#[serde(
+		rename = "enableSSHCA",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
 	pub enable_ssh_ca: Option<bool>,
 }
 
+impl StepProvisionerClaims {
+	/// Every duration in these claims that was present, but not a valid
+	/// duration, as the smallstep field name, and the value it was given.
+	/// Smallstep itself would refuse to load these, so they're usually a sign
+	/// of a config that was edited by hand.
+	#[must_use]
+	pub fn invalid_durations(&self) -> Vec<(&'static str, &JsonValue)> {
+		[
+			("minTLSCertDuration", &self.min_tls_dur),
+			("maxTLSCertDuration", &self.max_tls_dur),
+			("defaultTLSCertDuration", &self.default_tls_dur),
+			("minUserSSHCertDuration", &self.min_user_ssh_cert_dur),
+			("maxUserSSHCertDuration", &self.max_user_ssh_cert_dur),
+			(
+				"defaultUserSSHCertDuration",
+				&self.default_user_ssh_cert_duration,
+			),
+			("minHostSSHCertDuration", &self.min_host_ssh_cert_duration),
+			("maxHostSSHCertDuration", &self.max_host_ssh_cert_duration),
+			(
+				"defaultHostSSHCertDuration",
+				&self.default_host_ssh_cert_duration,
+			),
+		]
+		.iter()
+		.filter_map(|(field, dur)| dur.invalid().map(|raw| (*field, raw)))
+		.collect()
+	}
+}
+
 /// The provisioner field `options` is effectively a pair of key/value. This
 /// represents the value part of that key/value pair.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepProvisionerInnerOptions {
 	/// An optional template string.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub template: Option<String>,
 	/// An optional template file.
-	#[serde(rename = "templateFile", default)]
+	#[serde(
+		rename = "templateFile",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
 	pub template_file: Option<String>,
 	/// Optional values to render in the template.
-	#[serde(rename = "templateData", default)]
+	#[serde(
+		rename = "templateData",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
 	pub template_data: Option<JsonValue>,
 }
 
 /// Represents a set of options for a parictular provisioner.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepProvisionerOptions {
 	/// The SSH Options for this provisioner.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub ssh: Option<StepProvisionerInnerOptions>,
 	/// The X509 Options for this provisioner.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub x509: Option<StepProvisionerInnerOptions>,
 }
 
 /// Represents an actual provisioner from options, this can be deserailized
 /// with a: `deserialize_with` attribute.
-#[allow(clippy::pub_enum_variant_names)]
+///
+/// Serializing a provisioner writes the same layout smallstep uses, the
+/// provisioner's fields alongside its `type`, so a serialized list can be
+/// deserialized again, or handed back to smallstep. Unknown provisioners are
+/// written exactly as they were received.
+#[allow(clippy::enum_variant_names, clippy::large_enum_variant)]
 #[derive(Clone, Debug)]
 pub enum StepProvisioner {
 	/// An OIDC Provisioner.
@@ -230,4 +300,388 @@ pub enum StepProvisioner {
 	KubernetesServiceAccountProvisioner(StepK8SSAProvisioner),
 	/// A SSH Certificate based provisioner.
 	SshKeypairProvisioner(StepSSHPOPProvisioner),
+	/// A SCEP based provisioner.
+	ScepProvisioner(StepSCEPProvisioner),
+	/// A Nebula certificate based provisioner.
+	NebulaProvisioner(StepNebulaProvisioner),
+	/// A provisioner of a type tinystep doesn't know about. These are only
+	/// returned when parsing provisioners leniently (the default), so a new
+	/// type of provisioner on the server doesn't stop you listing the others.
+	Unknown {
+		/// The `type` the server reported for this provisioner.
+		typ: String,
+		/// The name given to this provisioner, or an empty string if it
+		/// didn't have one.
+		name: String,
+		/// The provisioner exactly as the server reported it.
+		raw: JsonValue,
+	},
+}
+
+/// The parts every provisioner has in common, implemented by every
+/// `Step*Provisioner`, and `StepProvisioner` itself so you don't need to
+/// match on the type of provisioner just to read these.
+pub trait ProvisionerCommon {
+	/// The name given to this provisioner to uniquely identify it.
+	fn name(&self) -> &str;
+
+	/// The type of this provisioner.
+	fn typ(&self) -> StepProvisionerType;
+
+	/// The claims overridden by this provisioner, if any.
+	fn claims(&self) -> Option<&StepProvisionerClaims>;
+
+	/// The templating options for this provisioner, if this type of
+	/// provisioner supports them, and they've been set.
+	fn options(&self) -> Option<&StepProvisionerOptions> {
+		None
+	}
+}
+
+/// Implement `ProvisionerCommon` for provisioners, those listed after
+/// `with_options:` also support options.
+macro_rules! impl_provisioner_common {
+	($($provisioner:ty),+ ; with_options: $($with_options:ty),+ $(,)?) => {
+		impl_provisioner_common!($($provisioner),+);
+		$(
+			impl ProvisionerCommon for $with_options {
+				fn name(&self) -> &str {
+					&self.name
+				}
+
+				fn typ(&self) -> StepProvisionerType {
+					self.typ
+				}
+
+				fn claims(&self) -> Option<&StepProvisionerClaims> {
+					self.claims.as_ref()
+				}
+
+				fn options(&self) -> Option<&StepProvisionerOptions> {
+					self.options.as_ref()
+				}
+			}
+		)+
+	};
+	($($provisioner:ty),+ $(,)?) => {
+		$(
+			impl ProvisionerCommon for $provisioner {
+				fn name(&self) -> &str {
+					&self.name
+				}
+
+				fn typ(&self) -> StepProvisionerType {
+					self.typ
+				}
+
+				fn claims(&self) -> Option<&StepProvisionerClaims> {
+					self.claims.as_ref()
+				}
+			}
+		)+
+	};
+}
+
+impl_provisioner_common!(
+	StepSSHPOPProvisioner;
+	with_options: StepOIDCProvisioner,
+	StepJWKProvisioner,
+	StepGCPProvisioner,
+	StepAWSProvisioner,
+	StepAzureProvisioner,
+	StepAcmeProvisioner,
+	StepX5CProvisioner,
+	StepK8SSAProvisioner,
+	StepSCEPProvisioner,
+	StepNebulaProvisioner,
+);
+
+/// Generate a method downcasting a `StepProvisioner` to a particular type
+/// of provisioner.
+macro_rules! provisioner_downcast {
+	($name:ident, $variant:ident, $provisioner:ty) => {
+		#[doc = concat!("This provisioner as a `", stringify!($provisioner), "`, if it is one.")]
+		#[must_use]
+		pub fn $name(&self) -> Option<&$provisioner> {
+			match self {
+				StepProvisioner::$variant(prov) => Some(prov),
+				_ => None,
+			}
+		}
+	};
+}
+
+impl StepProvisioner {
+	/// This provisioner as the parts every provisioner has in common, this is
+	/// `None` for an unknown provisioner.
+	#[must_use]
+	pub fn common(&self) -> Option<&dyn ProvisionerCommon> {
+		Some(match self {
+			StepProvisioner::OpenIDConnectProvisioner(prov) => prov,
+			StepProvisioner::JsonWebKeyProvisioner(prov) => prov,
+			StepProvisioner::GoogleCloudPlatformProvisioner(prov) => prov,
+			StepProvisioner::AmazonWebServicesProvisioner(prov) => prov,
+			StepProvisioner::AzureProvisioner(prov) => prov,
+			StepProvisioner::AcmeProvisioner(prov) => prov,
+			StepProvisioner::X509CertBundleProvisioner(prov) => prov,
+			StepProvisioner::KubernetesServiceAccountProvisioner(prov) => prov,
+			StepProvisioner::SshKeypairProvisioner(prov) => prov,
+			StepProvisioner::ScepProvisioner(prov) => prov,
+			StepProvisioner::NebulaProvisioner(prov) => prov,
+			StepProvisioner::Unknown { .. } => return None,
+		})
+	}
+
+	/// The name given to this provisioner to uniquely identify it.
+	#[must_use]
+	pub fn name(&self) -> &str {
+		match self {
+			StepProvisioner::Unknown { name, .. } => name,
+			_ => self.common().map_or("", |prov| prov.name()),
+		}
+	}
+
+	/// The type of this provisioner, `StepProvisionerType::Unknown` for an
+	/// unknown provisioner.
+	#[must_use]
+	pub fn typ(&self) -> StepProvisionerType {
+		self.common()
+			.map_or(StepProvisionerType::Unknown, |prov| prov.typ())
+	}
+
+	/// The claims overridden by this provisioner, if any. This is always
+	/// `None` for an unknown provisioner, whose claims are left in `raw`.
+	#[must_use]
+	pub fn claims(&self) -> Option<&StepProvisionerClaims> {
+		self.common().and_then(|prov| prov.claims())
+	}
+
+	/// The templating options for this provisioner, if this type of
+	/// provisioner supports them, and they've been set.
+	#[must_use]
+	pub fn options(&self) -> Option<&StepProvisionerOptions> {
+		self.common().and_then(|prov| prov.options())
+	}
+
+	/// If this is a provisioner of a type tinystep doesn't know about.
+	#[must_use]
+	pub fn is_unknown(&self) -> bool {
+		matches!(self, StepProvisioner::Unknown { .. })
+	}
+
+	provisioner_downcast!(as_oidc, OpenIDConnectProvisioner, StepOIDCProvisioner);
+	provisioner_downcast!(as_jwk, JsonWebKeyProvisioner, StepJWKProvisioner);
+	provisioner_downcast!(as_gcp, GoogleCloudPlatformProvisioner, StepGCPProvisioner);
+	provisioner_downcast!(as_aws, AmazonWebServicesProvisioner, StepAWSProvisioner);
+	provisioner_downcast!(as_azure, AzureProvisioner, StepAzureProvisioner);
+	provisioner_downcast!(as_acme, AcmeProvisioner, StepAcmeProvisioner);
+	provisioner_downcast!(as_x5c, X509CertBundleProvisioner, StepX5CProvisioner);
+	provisioner_downcast!(
+		as_k8ssa,
+		KubernetesServiceAccountProvisioner,
+		StepK8SSAProvisioner
+	);
+	provisioner_downcast!(as_sshpop, SshKeypairProvisioner, StepSSHPOPProvisioner);
+	provisioner_downcast!(as_scep, ScepProvisioner, StepSCEPProvisioner);
+	provisioner_downcast!(as_nebula, NebulaProvisioner, StepNebulaProvisioner);
+}
+
+impl ProvisionerCommon for StepProvisioner {
+	fn name(&self) -> &str {
+		StepProvisioner::name(self)
+	}
+
+	fn typ(&self) -> StepProvisionerType {
+		StepProvisioner::typ(self)
+	}
+
+	fn claims(&self) -> Option<&StepProvisionerClaims> {
+		StepProvisioner::claims(self)
+	}
+
+	fn options(&self) -> Option<&StepProvisionerOptions> {
+		StepProvisioner::options(self)
+	}
+}
+
+impl Serialize for StepProvisioner {
+	fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		match self {
+			StepProvisioner::OpenIDConnectProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::JsonWebKeyProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::GoogleCloudPlatformProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::AmazonWebServicesProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::AzureProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::AcmeProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::X509CertBundleProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::KubernetesServiceAccountProvisioner(prov) => {
+				prov.serialize(serializer)
+			}
+			StepProvisioner::SshKeypairProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::ScepProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::NebulaProvisioner(prov) => prov.serialize(serializer),
+			StepProvisioner::Unknown { raw, .. } => raw.serialize(serializer),
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for StepProvisioner {
+	/// Deserialize a single provisioner by its `type`, leniently the same as
+	/// `dynamic_provisioner_list`.
+	fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		let as_any = JsonValue::deserialize(deserializer)?;
+		crate::types::dynamic_provisioner_list(JsonValue::Array(vec![as_any]))
+			.map_err(D::Error::custom)?
+			.pop()
+			.ok_or_else(|| D::Error::custom("Failed to parse a provisioner"))
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+
+	/// Which of the `as_*` downcasts succeed for a provisioner.
+	fn downcasts(prov: &StepProvisioner) -> Vec<bool> {
+		vec![
+			prov.as_oidc().is_some(),
+			prov.as_jwk().is_some(),
+			prov.as_gcp().is_some(),
+			prov.as_aws().is_some(),
+			prov.as_azure().is_some(),
+			prov.as_acme().is_some(),
+			prov.as_x5c().is_some(),
+			prov.as_k8ssa().is_some(),
+			prov.as_sshpop().is_some(),
+			prov.as_scep().is_some(),
+			prov.as_nebula().is_some(),
+		]
+	}
+
+	#[test]
+	pub fn test_provisioner_common() {
+		let provisioners = [
+			(
+				StepProvisionerType::OpenIDConnect,
+				r#"{"type": "OIDC", "clientID": "id", "clientSecret": "secret",
+					"configurationEndpoint": "https://id.invalid/.well-known/openid-configuration"}"#,
+			),
+			(
+				StepProvisionerType::JsonWebKey,
+				r#"{"type": "JWK", "key": {"kty": "EC"}}"#,
+			),
+			(
+				StepProvisionerType::GoogleCloudPlatform,
+				r#"{"type": "GCP", "serviceAccounts": [], "projectIDs": [],
+					"disableCustomSANs": false, "disableTrustOnFirstUse": false}"#,
+			),
+			(
+				StepProvisionerType::AmazonWebServices,
+				r#"{"type": "AWS", "accounts": [], "disableCustomSANs": false,
+					"disableTrustOnFirstUse": false}"#,
+			),
+			(
+				StepProvisionerType::Azure,
+				r#"{"type": "Azure", "tenantId": "tenant", "resourceGroups": [],
+					"disableCustomSANs": false, "disableTrustOnFirstUse": false}"#,
+			),
+			(StepProvisionerType::Acme, r#"{"type": "ACME"}"#),
+			(
+				StepProvisionerType::X509CertBundle,
+				r#"{"type": "X5C", "roots": "cm9vdHM="}"#,
+			),
+			(
+				StepProvisionerType::KubernetesServiceAccount,
+				r#"{"type": "K8sSA", "publicKeys": "a2V5cw=="}"#,
+			),
+			(StepProvisionerType::SshKeypair, r#"{"type": "SSHPOP"}"#),
+			(StepProvisionerType::Scep, r#"{"type": "SCEP"}"#),
+			(
+				StepProvisionerType::Nebula,
+				r#"{"type": "Nebula", "roots": "cm9vdHM="}"#,
+			),
+		];
+
+		for (idx, (typ, fields)) in provisioners.iter().enumerate() {
+			let mut raw = serde_json::from_str::<JsonValue>(fields).unwrap();
+			raw["name"] = format!("prov-{}", idx).into();
+			raw["claims"] = serde_json::json!({"minTLSCertDuration": "5m"});
+			raw["options"] = serde_json::json!({"x509": {"template": "{}"}});
+			let prov = serde_json::from_value::<StepProvisioner>(raw).unwrap();
+
+			assert_eq!(prov.name(), format!("prov-{}", idx));
+			assert_eq!(prov.typ(), *typ);
+			assert!(prov.claims().is_some(), "{:?}", typ);
+			// Every provisioner but SSHPOP supports templates.
+			assert_eq!(
+				prov.options().is_some(),
+				*typ != StepProvisionerType::SshKeypair,
+				"{:?}",
+				typ
+			);
+			assert!(!prov.is_unknown());
+
+			let common = prov.common().unwrap();
+			assert_eq!(common.name(), prov.name());
+			assert_eq!(common.typ(), *typ);
+
+			let mut expected = vec![false; provisioners.len()];
+			expected[idx] = true;
+			assert_eq!(downcasts(&prov), expected, "{:?}", typ);
+		}
+
+		let unknown = serde_json::from_str::<StepProvisioner>(
+			r#"{"type": "FUTURE", "name": "future", "claims": {"minTLSCertDuration": "5m"}}"#,
+		)
+		.unwrap();
+		assert!(unknown.is_unknown());
+		assert!(unknown.common().is_none());
+		assert_eq!(unknown.name(), "future");
+		assert_eq!(unknown.typ(), StepProvisionerType::Unknown);
+		assert!(unknown.claims().is_none());
+		assert!(unknown.options().is_none());
+		assert_eq!(downcasts(&unknown), vec![false; provisioners.len()]);
+
+		assert!(serde_json::from_str::<StepAcmeProvisioner>(
+			r#"{"type": "FUTURE", "name": "future"}"#
+		)
+		.is_err());
+		assert!(serde_json::from_str::<StepProvisionerType>(r#""Unknown""#).is_err());
+	}
+
+	#[test]
+	pub fn test_malformed_claims_in_response() {
+		let page = serde_json::from_str::<crate::types::StepProvisionersResponseRaw>(
+			r#"{"provisioners": [
+				{"type": "JWK", "name": "jwk", "key": {"kty": "EC"},
+					"claims": {"minTLSCertDuration": "5m", "maxTLSCertDuration": "24hh"}},
+				{"type": "AWS", "name": "aws", "accounts": [], "disableCustomSANs": false,
+					"disableTrustOnFirstUse": false, "instanceAge": "1 hour"}
+			], "nextCursor": ""}"#,
+		)
+		.unwrap();
+
+		let claims = page.provisioners[0].claims().unwrap();
+		assert!(claims.min_tls_dur.valid().is_some());
+		assert!(claims.max_tls_dur.valid().is_none());
+		assert!(!claims.max_tls_dur.is_absent());
+		assert_eq!(
+			claims.invalid_durations(),
+			vec![("maxTLSCertDuration", &JsonValue::from("24hh"))]
+		);
+		assert_eq!(
+			page.provisioners[1]
+				.as_aws()
+				.unwrap()
+				.instance_age
+				.invalid(),
+			Some(&JsonValue::from("1 hour"))
+		);
+	}
 }
@@ -0,0 +1,34 @@
+//! All of the types for a Nebula Provisioner, these are split out because
+//! they're pretty large types so we split it to it's own module for
+//! readability sake.
+
+use crate::types::StepProvisionerType;
+use serde::{Deserialize, Serialize};
+
+/// Provision certificates using a certificate issued by a Nebula overlay
+/// network's certificate authority to provide authentication so we know
+/// which certs to issue. <https://smallstep.com/docs/step-ca/provisioners#nebula>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepNebulaProvisioner {
+	/// The type of this provisioner, will always be:
+	/// `StepProvisionerType::Nebula`.
+	#[serde(rename = "type")]
+	pub typ: StepProvisionerType,
+	/// The name given to this provisioner to uniquely identify it.
+	pub name: String,
+	/// The base64 encoded bundle of PEM encoded Nebula CA certificates, that
+	/// certificates presented to this provisioner must be signed by.
+	pub roots: String,
+	/// An override of "Claims" for this provisioner. This will allow the
+	/// provisioner to manually specify the default/min/max tls certificate
+	/// issue time if specified.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub claims: Option<super::StepProvisionerClaims>,
+	/// The templating options for certificates issued by this provisioner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
+}
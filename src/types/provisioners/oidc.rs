@@ -3,12 +3,12 @@
 //! readability sake.
 
 use crate::types::StepProvisionerType;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Provision certificates using OIDC to provide authentication so we know
 /// which certs to issue, and who issues them.
 /// <https://smallstep.com/docs/step-ca/configuration#oauthoidc-single-sign-on>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepOIDCProvisioner {
 	/// The type of this provisioner, will always be:
 	/// `StepProvisionerType::OpenIDConnect`.
@@ -47,22 +47,22 @@ pub struct StepOIDCProvisioner {
 	pub configuration_endpoint: String,
 	/// The OAuth2 Tenant ID used by smallstep. This is only used for Azure AD
 	/// where a Tenant ID is required.
-	#[serde(rename = "tenantID", default)]
+	#[serde(rename = "tenantID", default, skip_serializing_if = "Option::is_none")]
 	pub tenant_id: Option<String>,
 	/// A potential list of hand configured admins who are able to get
 	/// certificates with custom SANs. If a user is not an admin, it will
 	/// only be able to get a certificate with its email in it.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub admins: Option<Vec<String>>,
 	/// A potential hand configured list of domains that are actually allowed
 	/// to authenticate with OIDC. If present, only users with email from one
 	/// of the following domains will be allowed to authenticate.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub domains: Option<Vec<String>>,
 	/// A potential hand configured list of groups that are actually allowed to
 	/// authenticate with OIDC. If present, only users belonging to the groups
 	/// in this list will be able to authenticate.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub groups: Option<Vec<String>>,
 	/// An optional loopback address for the client to use when authenticating
 	/// with OIDC.
@@ -73,16 +73,24 @@ pub struct StepOIDCProvisioner {
 	/// this address.
 	///
 	/// The format is documented as being: `:port`, or: `host:port`.
-	#[serde(rename = "listenAddress", default)]
+	#[serde(
+		rename = "listenAddress",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
 	pub listen_address: Option<String>,
 	/// An override of "Claims" for this provisioner. This will allow the
 	/// provisioner to manually specify the default/min/max tls certificate
 	/// issue time if specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub claims: Option<super::StepProvisionerClaims>,
 	/// An extra set of options for this provisioner specifically. These options
 	/// are options that should get passed during the certificate creation
 	/// flow, and are internal options to that flow.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
 }
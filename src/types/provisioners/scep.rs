@@ -0,0 +1,61 @@
+//! All of the types for a SCEP Provisioner, these are split out because they're
+//! pretty large types so we split it to it's own module for readability sake.
+
+use crate::types::StepProvisionerType;
+use serde::{Deserialize, Serialize};
+
+/// Provision certificates using the Simple Certificate Enrollment Protocol
+/// (SCEP), commonly used by network devices, and mobile device management.
+/// <https://smallstep.com/docs/step-ca/provisioners#scep>
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StepSCEPProvisioner {
+	/// The type of this provisioner, will always be:
+	/// `StepProvisionerType::Scep`.
+	#[serde(rename = "type")]
+	pub typ: StepProvisionerType,
+	/// The name given to this provisioner to uniquely identify it.
+	pub name: String,
+	/// If the common name of the certificate should be forced to be one of
+	/// the SANs.
+	#[serde(rename = "forceCN", default)]
+	pub force_cn: bool,
+	/// The shared secret clients must present when requesting a certificate.
+	#[serde(rename = "challenge", default, skip_serializing_if = "Option::is_none")]
+	pub challenge_password: Option<String>,
+	/// The SCEP capabilities to advertise to clients, when not specified
+	/// smallstep advertises its defaults.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub capabilities: Option<Vec<String>>,
+	/// If the root certificate should be included in the `GetCACert` response,
+	/// in addition to the intermediate.
+	#[serde(rename = "includeRoot", default)]
+	pub include_root: bool,
+	/// The minimum length of public key (in bits) accepted from clients.
+	#[serde(
+		rename = "minimumPublicKeyLength",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub minimum_public_key_length: Option<u32>,
+	/// The algorithm used to encrypt responses to clients, as the identifier
+	/// smallstep uses: `0` DES-CBC, `1` AES-128-CBC, `2` AES-256-CBC,
+	/// `3` AES-128-GCM, `4` AES-256-GCM.
+	#[serde(
+		rename = "encryptionAlgorithmIdentifier",
+		default,
+		skip_serializing_if = "Option::is_none"
+	)]
+	pub encryption_algorithm_identifier: Option<u32>,
+	/// An override of "Claims" for this provisioner. This will allow the
+	/// provisioner to manually specify the default/min/max tls certificate
+	/// issue time if specified.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub claims: Option<super::StepProvisionerClaims>,
+	/// The templating options for certificates issued by this provisioner.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub options: Option<super::StepProvisionerOptions>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
+}
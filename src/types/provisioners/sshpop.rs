@@ -3,12 +3,12 @@
 //! module for readability sake.
 
 use crate::types::StepProvisionerType;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Provision certificates using SSH Certificate to provide
 /// authentication so we know which certs to issue.
 /// <https://smallstep.com/docs/step-ca/configuration#sshpop-ssh-certificate>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct StepSSHPOPProvisioner {
 	/// The type of this provisioner, will always be:
 	/// `StepProvisionerType::SshKeypair`.
@@ -19,6 +19,10 @@ pub struct StepSSHPOPProvisioner {
 	/// An override of "Claims" for this provisioner. This will allow the
 	/// provisioner to manually specify the default/min/max tls certificate
 	/// issue time if specified.
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "Option::is_none")]
 	pub claims: Option<super::StepProvisionerClaims>,
+	/// Any fields smallstep sent that tinystep doesn't know about (yet), kept
+	/// so they're written back out.
+	#[serde(flatten)]
+	pub extra: serde_json::Map<String, serde_json::Value>,
 }
@@ -0,0 +1,167 @@
+//! Figuring out what a smallstep instance supports from the version it
+//! reports.
+//!
+//! Newer parts of the smallstep API simply 404 on older servers, which makes
+//! for a very confusing error. Tinystep doesn't wrap any of those parts of
+//! the API yet, so nothing here is checked automatically. If you're calling
+//! one of them yourself (e.g. with `TinystepClient::construct_url`), call
+//! `TinystepClient::ensure_supported` first to get an
+//! `Error::UnsupportedServerVersion` up front instead.
+
+use crate::{
+	error::{Error, Result},
+	TinystepClient,
+};
+use semver::Version;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use tracing::warn;
+
+/// Parts of the smallstep API that only exist on newer servers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ServerFeature {
+	/// The admin API for managing provisioners, and admins remotely.
+	AdminApi,
+	/// Rekeying SSH certificates: `/ssh/rekey`.
+	SshRekey,
+	/// External account binding for ACME provisioners.
+	AcmeExternalAccountBinding,
+}
+
+impl ServerFeature {
+	/// The first version of smallstep that supports this feature.
+	#[must_use]
+	pub fn minimum_version(self) -> Version {
+		match self {
+			ServerFeature::AdminApi => Version::new(0, 18, 0),
+			ServerFeature::SshRekey => Version::new(0, 14, 0),
+			ServerFeature::AcmeExternalAccountBinding => Version::new(0, 18, 1),
+		}
+	}
+}
+
+impl Display for ServerFeature {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		match self {
+			ServerFeature::AdminApi => write!(f, "The admin API"),
+			ServerFeature::SshRekey => write!(f, "SSH rekey"),
+			ServerFeature::AcmeExternalAccountBinding => {
+				write!(f, "ACME external account binding")
+			}
+		}
+	}
+}
+
+/// Parse the version smallstep reports from `/version`. Releases report a
+/// plain version, sometimes with a leading `v`, while development builds
+/// report something that isn't a version at all, which we can't reason about.
+pub(crate) fn parse_remote_version(version: &str) -> Option<Version> {
+	let trimmed = version.trim();
+	match Version::parse(trimmed.strip_prefix('v').unwrap_or(trimmed)) {
+		Ok(parsed) => Some(parsed),
+		Err(err) => {
+			warn!(
+				"Smallstep reported version: {} which isn't semver ({}), assuming every feature is supported",
+				version, err
+			);
+			None
+		}
+	}
+}
+
+impl TinystepClient {
+	/// The version of smallstep the client is talking to, this is `None`
+	/// when the version probe was skipped, or smallstep reported a version we
+	/// couldn't parse (like a development build).
+	#[must_use]
+	pub fn remote_version(&self) -> Option<&Version> {
+		self.remote_version.as_ref()
+	}
+
+	/// If the smallstep instance requires client authentication for most of
+	/// its API, as reported by `/version`. This is `None` when the version
+	/// probe was skipped. Useful for deciding if you need to obtain an
+	/// identity before doing anything else.
+	#[must_use]
+	pub fn requires_client_authentication(&self) -> Option<bool> {
+		self.require_client_authentication
+	}
+
+	/// If the smallstep instance supports a feature. When we don't know the
+	/// remote version we assume it does, and let the server tell us otherwise.
+	#[must_use]
+	pub fn supports(&self, feature: ServerFeature) -> bool {
+		self.ensure_supported(feature).is_ok()
+	}
+
+	/// Ensure the smallstep instance supports a feature before calling it.
+	/// This is never called for you, call it before making a request that
+	/// needs `feature`.
+	///
+	/// # Errors
+	///
+	/// - `Error::UnsupportedServerVersion` when the remote version is older
+	///   than the first version supporting `feature`.
+	pub fn ensure_supported(&self, feature: ServerFeature) -> Result<()> {
+		let minimum = feature.minimum_version();
+		match &self.remote_version {
+			// Pre-releases of the minimum version sort before it, but usually
+			// already contain the feature.
+			Some(version)
+				if Version::new(version.major, version.minor, version.patch) < minimum =>
+			{
+				Err(Error::UnsupportedServerVersion {
+					feature,
+					version: version.clone(),
+					minimum,
+				})
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod unit_tests {
+	use super::*;
+	use std::path::PathBuf;
+
+	#[test]
+	pub fn test_parse_remote_version() {
+		assert_eq!(parse_remote_version("0.15.3"), Some(Version::new(0, 15, 3)));
+		assert_eq!(
+			parse_remote_version("v0.18.1"),
+			Some(Version::new(0, 18, 1))
+		);
+		assert_eq!(
+			parse_remote_version("0.18.0-rc1").map(|v| v.pre.to_string()),
+			Some("rc1".to_owned())
+		);
+		assert_eq!(parse_remote_version("0000000-dev"), None);
+	}
+
+	#[test]
+	pub fn test_ensure_supported() {
+		let mut client = TinystepClient::builder()
+			.base_url("https://ca.internal")
+			.ca_file(PathBuf::from("/dev/null"))
+			.skip_version_probe(true)
+			.build()
+			.unwrap();
+		assert!(client.supports(ServerFeature::AdminApi));
+
+		client.remote_version = parse_remote_version("0.17.6");
+		assert!(client.supports(ServerFeature::SshRekey));
+		match client.ensure_supported(ServerFeature::AdminApi) {
+			Err(Error::UnsupportedServerVersion {
+				feature, minimum, ..
+			}) => {
+				assert_eq!(feature, ServerFeature::AdminApi);
+				assert_eq!(minimum, Version::new(0, 18, 0));
+			}
+			other => panic!("Expected an unsupported version error, got: {:?}", other),
+		}
+
+		client.remote_version = parse_remote_version("0.18.0-rc1");
+		assert!(client.supports(ServerFeature::AdminApi));
+	}
+}